@@ -1,5 +1,14 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::{collections::HashMap, net::Ipv4Addr};
+
+/// Reply types assumed to mark a DNS-over-HTTPS query
+///
+/// FTL does not report DoH separately yet, so nothing matches today.
+/// Update these (and [`DOH_STATUSES`]) once FTL exposes the values.
+pub const DOH_REPLY_TYPES: &[&str] = &["DOH"];
+
+/// Query statuses assumed to mark a DNS-over-HTTPS query
+pub const DOH_STATUSES: &[&str] = &["DOH"];
 
 /// Authentication response from Pi-hole API
 #[derive(Debug, Deserialize)]
@@ -10,7 +19,10 @@ pub struct AuthResponse {
 /// Session information after authentication
 #[derive(Debug, Deserialize)]
 pub struct SessionInfo {
-    pub sid: String,
+    pub valid: bool,
+    /// Absent when the Pi-hole has no password set
+    pub sid: Option<String>,
+    pub message: Option<String>,
 }
 
 /// Authentication request payload
@@ -51,6 +63,294 @@ pub struct ClientStats {
 #[derive(Debug, Deserialize)]
 pub struct GravityStats {
     pub domains_being_blocked: u64,
+    /// Unix time gravity was last rebuilt, 0 if FTL does not report it
+    #[serde(default)]
+    pub last_update: u64,
+}
+
+/// Long-term history response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct HistoricalDataResponse {
+    pub history: Vec<DailyStats>,
+}
+
+/// Aggregated query counts for a single history interval
+#[derive(Debug, Deserialize)]
+pub struct DailyStats {
+    pub timestamp: u64,
+    #[serde(rename = "total")]
+    pub queries: u64,
+    pub blocked: u64,
+}
+
+/// Groups response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct GroupsResponse {
+    pub groups: Vec<GroupInfo>,
+}
+
+/// Information about a client group
+#[derive(Debug, Deserialize)]
+pub struct GroupInfo {
+    pub id: u64,
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Lists (allow/block lists) response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct ListsResponse {
+    pub lists: Vec<ListInfo>,
+}
+
+/// Information about a single allow/block list
+#[derive(Debug, Deserialize)]
+pub struct ListInfo {
+    pub id: u64,
+    pub address: String,
+    #[serde(rename = "type")]
+    pub list_type: String,
+    pub enabled: bool,
+    pub groups: Vec<u64>,
+    pub number: u64,
+    pub invalid_domains: u64,
+    pub date_updated: u64,
+}
+
+/// Custom allow/deny domains response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct DomainsResponse {
+    pub domains: Vec<DomainInfo>,
+}
+
+/// A manually allowed or denied domain
+#[derive(Debug, Deserialize)]
+pub struct DomainInfo {
+    pub domain: String,
+    #[serde(rename = "type")]
+    pub domain_type: String,
+    pub kind: String,
+    pub date_modified: u64,
+}
+
+/// Domain search response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct SearchResponse {
+    pub search: SearchResults,
+}
+
+/// Allow/deny list and gravity entries matching a searched domain
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchResults {
+    #[serde(default)]
+    pub domains: Vec<SearchDomain>,
+    #[serde(default)]
+    pub gravity: Vec<SearchGravity>,
+}
+
+/// A manually allowed or denied domain (or regex) matching a search
+#[derive(Debug, Deserialize)]
+pub struct SearchDomain {
+    #[serde(rename = "type")]
+    pub domain_type: String,
+    pub kind: String,
+}
+
+/// A gravity list entry matching a search
+#[derive(Debug, Deserialize)]
+pub struct SearchGravity {
+    #[serde(rename = "type")]
+    pub list_type: String,
+}
+
+/// Whether a domain would be blocked, and by which kind of list
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct DomainCheck {
+    pub blocked: bool,
+    /// `gravity`, `whitelist`, `blacklist` or `regex`; absent when no list matches
+    pub list: Option<&'static str>,
+}
+
+impl SearchResults {
+    /// Decide the outcome with FTL's precedence: exact allow, exact deny,
+    /// regex allow, regex deny, then gravity (where allow entries win)
+    pub fn check(&self) -> DomainCheck {
+        let has_domain = |domain_type: &str, kind: &str| {
+            self.domains
+                .iter()
+                .any(|d| d.domain_type == domain_type && d.kind == kind)
+        };
+        let has_gravity = |list_type: &str| self.gravity.iter().any(|g| g.list_type == list_type);
+
+        let (blocked, list) = if has_domain("allow", "exact") {
+            (false, "whitelist")
+        } else if has_domain("deny", "exact") {
+            (true, "blacklist")
+        } else if has_domain("allow", "regex") {
+            (false, "regex")
+        } else if has_domain("deny", "regex") {
+            (true, "regex")
+        } else if has_gravity("allow") {
+            (false, "whitelist")
+        } else if has_gravity("block") {
+            (true, "gravity")
+        } else {
+            return DomainCheck {
+                blocked: false,
+                list: None,
+            };
+        };
+        DomainCheck {
+            blocked,
+            list: Some(list),
+        }
+    }
+}
+
+/// DHCP leases response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct DhcpStatusResponse {
+    /// Not part of the leases response, filled in from [`DhcpConfigResponse`]
+    #[serde(skip)]
+    pub enabled: bool,
+    pub leases: Vec<DhcpLease>,
+}
+
+/// A single DHCP lease
+#[derive(Debug, Deserialize)]
+pub struct DhcpLease {
+    pub ip: String,
+    #[serde(rename = "hwaddr")]
+    pub mac: String,
+    #[serde(rename = "name")]
+    pub hostname: String,
+    #[serde(rename = "expires")]
+    pub expiry: u64,
+}
+
+/// DHCP configuration response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct DhcpConfigResponse {
+    pub config: DhcpConfigSection,
+}
+
+/// The `config` object of [`DhcpConfigResponse`]
+#[derive(Debug, Deserialize)]
+pub struct DhcpConfigSection {
+    pub dhcp: DhcpConfig,
+}
+
+/// DHCP server configuration
+#[derive(Debug, Deserialize)]
+pub struct DhcpConfig {
+    pub active: bool,
+}
+
+/// DNS configuration response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct DnsConfigResponse {
+    pub config: DnsConfigSection,
+}
+
+/// The `config` object of [`DnsConfigResponse`]
+#[derive(Debug, Deserialize)]
+pub struct DnsConfigSection {
+    pub dns: DnsConfig,
+}
+
+/// DNS server configuration
+#[derive(Debug, Deserialize)]
+pub struct DnsConfig {
+    /// Conditional forwarding targets as `enabled,cidr,server[,domain]`
+    #[serde(rename = "revServers", default)]
+    pub rev_servers: Vec<String>,
+}
+
+impl DnsConfig {
+    /// Number of conditional forwarding targets that are switched on
+    pub fn enabled_rev_servers(&self) -> usize {
+        self.rev_servers
+            .iter()
+            .filter(|server| server.split(',').next() == Some("true"))
+            .count()
+    }
+}
+
+/// Component versions response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct VersionResponse {
+    pub version: VersionInfo,
+}
+
+/// Versions of the Pi-hole components
+#[derive(Debug, Deserialize)]
+pub struct VersionInfo {
+    /// FTL serves the API, so its version is the API version
+    pub ftl: ComponentVersion,
+}
+
+/// Version details of a single Pi-hole component
+#[derive(Debug, Deserialize)]
+pub struct ComponentVersion {
+    pub local: LocalVersion,
+}
+
+/// The locally installed version of a component
+#[derive(Debug, Deserialize)]
+pub struct LocalVersion {
+    pub version: String,
+}
+
+/// FTL process information response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct FtlInfoResponse {
+    pub ftl: FtlInfo,
+}
+
+/// Resource usage of the FTL process
+#[derive(Debug, Deserialize)]
+pub struct FtlInfo {
+    /// CPU usage of FTL, in percent of one core
+    #[serde(rename = "%cpu")]
+    pub cpu_percent: f64,
+    /// Resident memory of FTL, in percent of total RAM
+    #[serde(rename = "%mem")]
+    pub mem_percent: f64,
+}
+
+/// Long-term query database information response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct DatabaseInfoResponse {
+    /// Number of queries stored in the database
+    pub queries: u64,
+    /// Unix time of the oldest stored query, 0 when the database is empty
+    #[serde(default)]
+    pub earliest_timestamp: f64,
+}
+
+/// Host system information response from Pi-hole API
+#[derive(Debug, Deserialize)]
+pub struct SystemInfoResponse {
+    pub system: SystemInfo,
+}
+
+/// The `system` object of [`SystemInfoResponse`]
+#[derive(Debug, Deserialize)]
+pub struct SystemInfo {
+    pub memory: SystemMemory,
+}
+
+/// Memory of the Pi-hole host
+#[derive(Debug, Deserialize)]
+pub struct SystemMemory {
+    pub ram: RamInfo,
+}
+
+/// RAM of the Pi-hole host
+#[derive(Debug, Deserialize)]
+pub struct RamInfo {
+    /// Total RAM in KiB
+    pub total: u64,
 }
 
 /// Upstream servers response from Pi-hole API
@@ -62,38 +362,262 @@ pub struct UpstreamsResponse {
 /// Information about an upstream DNS server
 #[derive(Debug, Deserialize)]
 pub struct UpstreamInfo {
+    #[serde(default = "default_unknown")]
     pub ip: String,
+    #[serde(default = "default_unknown")]
     pub name: String,
+    #[serde(default = "default_upstream_port")]
     pub port: i16,
+    #[serde(default)]
     pub count: u64,
 }
 
 /// Queries response from Pi-hole API
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct QueriesResponse {
     pub queries: Vec<QueryInfo>,
 }
 
 /// Information about a single DNS query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct QueryInfo {
-    #[serde(rename = "type")]
+    #[serde(default)]
+    pub id: Option<u64>,
+    #[serde(rename = "type", default = "default_unknown")]
     pub query_type: String,
+    #[serde(default = "default_unknown")]
+    pub domain: String,
+    #[serde(default = "default_unknown")]
     pub status: String,
+    #[serde(default)]
     pub reply: ReplyInfo,
+    #[serde(default)]
     pub client: ClientInfo,
+    #[serde(default)]
     pub upstream: Option<String>,
+    /// Address in the reply, when FTL reports one
+    #[serde(default)]
+    pub reply_address: Option<String>,
+    /// DNS response code, when FTL reports one
+    #[serde(default)]
+    pub rcode: Option<String>,
+}
+
+impl QueryInfo {
+    /// Whether FTL marked this query as DNS-over-HTTPS
+    pub fn is_doh(&self) -> bool {
+        DOH_REPLY_TYPES.contains(&self.reply.reply_type.as_str())
+            || DOH_STATUSES.contains(&self.status.as_str())
+    }
+
+    /// DNS response code of the reply, derived from the reply type unless FTL reports it
+    pub fn rcode(&self) -> Option<&str> {
+        self.rcode
+            .as_deref()
+            .or_else(|| reply_rcode(&self.reply.reply_type))
+    }
+
+    /// Whether the reply pointed at an RFC 1918 address, a sign of DNS rebinding
+    pub fn is_private_reply(&self) -> bool {
+        self.reply_address
+            .as_deref()
+            .and_then(|address| address.parse::<Ipv4Addr>().ok())
+            .is_some_and(|address| address.is_private())
+    }
 }
 
 /// Reply information for a DNS query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ReplyInfo {
-    #[serde(rename = "type")]
+    #[serde(rename = "type", default = "default_unknown")]
     pub reply_type: String,
 }
 
+impl Default for ReplyInfo {
+    fn default() -> Self {
+        Self {
+            reply_type: default_unknown(),
+        }
+    }
+}
+
 /// Client information for a DNS query
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ClientInfo {
+    #[serde(default = "default_unknown")]
     pub ip: String,
 }
+
+impl Default for ClientInfo {
+    fn default() -> Self {
+        Self {
+            ip: default_unknown(),
+        }
+    }
+}
+
+/// DNS response code implied by an FTL reply type, `None` while no reply has been sent
+///
+/// FTL reports RCODEs other than these as the `OTHER` reply type.
+pub fn reply_rcode(reply_type: &str) -> Option<&'static str> {
+    match reply_type {
+        "NONE" | "UNKNOWN" => None,
+        "NXDOMAIN" => Some("NXDOMAIN"),
+        "SERVFAIL" => Some("SERVFAIL"),
+        "REFUSED" => Some("REFUSED"),
+        "NOTIMP" => Some("NOTIMP"),
+        "NODATA" | "IP" | "CNAME" | "DOMAIN" | "RRNAME" | "BLOB" | "DNSSEC" => Some("NOERROR"),
+        _ => Some("OTHER"),
+    }
+}
+
+/// Fallback for string fields missing from older FTL responses
+fn default_unknown() -> String {
+    "UNKNOWN".to_string()
+}
+
+/// Fallback upstream port, matching FTL's pseudo upstreams
+fn default_upstream_port() -> i16 {
+    -1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_session() {
+        let response: AuthResponse = serde_json::from_str(
+            r#"{"session": {"valid": false, "totp": false, "sid": null, "validity": -1, "message": "password incorrect"}}"#,
+        )
+        .unwrap();
+
+        assert!(!response.session.valid);
+        assert_eq!(response.session.sid, None);
+        assert_eq!(
+            response.session.message.as_deref(),
+            Some("password incorrect")
+        );
+    }
+
+    #[test]
+    fn test_stats_summary_keys() {
+        // Trimmed /api/stats/summary response; FTL uses snake_case keys
+        let stats: StatsResponse = serde_json::from_str(
+            r#"{
+                "queries": {
+                    "total": 7497,
+                    "blocked": 3465,
+                    "percent_blocked": 46.2,
+                    "unique_domains": 445,
+                    "forwarded": 4009,
+                    "cached": 23,
+                    "frequency": 1.1,
+                    "types": {"A": 3643, "AAAA": 999},
+                    "status": {"GRAVITY": 3465, "FORWARDED": 4009},
+                    "replies": {"IP": 3998, "NODATA": 12}
+                },
+                "clients": {"active": 10, "total": 18},
+                "gravity": {"domains_being_blocked": 104584, "last_update": 1725194639},
+                "took": 0.003
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(stats.queries.total, 7497);
+        assert_eq!(stats.queries.unique_domains, 445);
+        assert_eq!(stats.queries.status["GRAVITY"], 3465);
+        assert_eq!(stats.clients.active, 10);
+        assert_eq!(stats.gravity.domains_being_blocked, 104584);
+        assert_eq!(stats.gravity.last_update, 1725194639);
+
+        let lease: DhcpLease = serde_json::from_str(
+            r#"{"expires": 1725232131, "name": "laptop", "hwaddr": "00:11:22:33:44:55", "ip": "192.168.0.10", "clientid": "*"}"#,
+        )
+        .unwrap();
+        assert_eq!(lease.mac, "00:11:22:33:44:55");
+        assert_eq!(lease.hostname, "laptop");
+    }
+
+    #[test]
+    fn test_search_check() {
+        let search: SearchResponse = serde_json::from_str(
+            r#"{"search": {
+                "domains": [{"domain": "ads.example.com", "type": "deny", "kind": "regex"}],
+                "gravity": [{"domain": "ads.example.com", "type": "block", "address": "https://list"}],
+                "results": {"total": 2}
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            search.search.check(),
+            DomainCheck {
+                blocked: true,
+                list: Some("regex"),
+            }
+        );
+
+        let search: SearchResponse =
+            serde_json::from_str(r#"{"search": {"gravity": [{"type": "block"}]}}"#).unwrap();
+        assert_eq!(search.search.check().list, Some("gravity"));
+
+        assert_eq!(
+            SearchResults::default().check(),
+            DomainCheck {
+                blocked: false,
+                list: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_rev_servers() {
+        let config: DnsConfigResponse = serde_json::from_str(
+            r#"{"config": {"dns": {"revServers": [
+                "true,192.168.0.0/24,192.168.0.1,lan",
+                "false,10.0.0.0/8,10.0.0.1"
+            ]}}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.config.dns.rev_servers.len(), 2);
+        assert_eq!(config.config.dns.enabled_rev_servers(), 1);
+    }
+
+    #[test]
+    fn test_query_info_defaults() {
+        let query: QueryInfo = serde_json::from_str(r#"{"type": "A"}"#).unwrap();
+
+        assert_eq!(query.id, None);
+        assert_eq!(query.query_type, "A");
+        assert_eq!(query.status, "UNKNOWN");
+        assert_eq!(query.reply.reply_type, "UNKNOWN");
+        assert_eq!(query.client.ip, "UNKNOWN");
+        assert_eq!(query.upstream, None);
+        assert!(!query.is_private_reply());
+        assert_eq!(query.rcode(), None);
+
+        let query: QueryInfo = serde_json::from_str(r#"{"reply": {"type": "NODATA"}}"#).unwrap();
+        assert_eq!(query.rcode(), Some("NOERROR"));
+        let query: QueryInfo =
+            serde_json::from_str(r#"{"reply": {"type": "OTHER"}, "rcode": "FORMERR"}"#).unwrap();
+        assert_eq!(query.rcode(), Some("FORMERR"));
+
+        for (address, private) in [
+            ("10.1.2.3", true),
+            ("172.20.0.1", true),
+            ("172.32.0.1", false),
+            ("192.168.1.1", true),
+            ("8.8.8.8", false),
+        ] {
+            let query: QueryInfo =
+                serde_json::from_value(serde_json::json!({ "reply_address": address })).unwrap();
+            assert_eq!(query.is_private_reply(), private, "{address}");
+        }
+
+        let upstream: UpstreamInfo = serde_json::from_str(r#"{"ip": "1.1.1.1"}"#).unwrap();
+        assert_eq!(upstream.name, "UNKNOWN");
+        assert_eq!(upstream.port, -1);
+        assert_eq!(upstream.count, 0);
+    }
+}
@@ -0,0 +1,85 @@
+use std::{net::IpAddr, path::Path, sync::Mutex};
+
+use ahash::HashMap;
+use maxminddb::{Reader, geoip2};
+
+use crate::Result;
+
+/// Label value used when an address has no ASN record, e.g. private ranges
+const UNKNOWN: &str = "UNKNOWN";
+
+/// Autonomous system a client address belongs to
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct AsnInfo {
+    pub asn: String,
+    pub org: String,
+}
+
+impl AsnInfo {
+    fn unknown() -> Self {
+        Self {
+            asn: UNKNOWN.to_string(),
+            org: UNKNOWN.to_string(),
+        }
+    }
+}
+
+/// Looks up client addresses in a MaxMind GeoLite2 ASN database
+pub struct AsnLookup {
+    reader: Reader<Vec<u8>>,
+    /// The database is loaded once, so lookups never go stale
+    cache: Mutex<HashMap<String, AsnInfo>>,
+}
+
+impl AsnLookup {
+    /// Load the `.mmdb` database at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            reader: Reader::open_readfile(path)?,
+            cache: Mutex::new(HashMap::default()),
+        })
+    }
+
+    /// ASN and organization for `ip`, or `UNKNOWN` when there is no record
+    pub fn lookup(&self, ip: &str) -> AsnInfo {
+        if let Some(info) = self.cache.lock().unwrap().get(ip) {
+            return info.clone();
+        }
+
+        let info = ip
+            .parse::<IpAddr>()
+            .ok()
+            .and_then(|addr| self.reader.lookup::<geoip2::Asn>(addr).ok().flatten())
+            .map_or_else(AsnInfo::unknown, |record| AsnInfo {
+                asn: record
+                    .autonomous_system_number
+                    .map_or_else(|| UNKNOWN.to_string(), |asn| asn.to_string()),
+                org: record
+                    .autonomous_system_organization
+                    .unwrap_or(UNKNOWN)
+                    .to_string(),
+            });
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(ip.to_string(), info.clone());
+        info
+    }
+}
+
+impl std::fmt::Debug for AsnLookup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsnLookup").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_missing_database() {
+        let err = AsnLookup::open(Path::new("/nonexistent/GeoLite2-ASN.mmdb")).unwrap_err();
+        assert!(err.to_string().contains("ASN database"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::{fs, path::Path};
+
+use ahash::HashMap;
+
+use crate::{PiholeError, Result};
+
+/// Category reported for domains that match no configured suffix
+pub const UNCATEGORIZED: &str = "other";
+
+/// Maps query domains to categories by their longest configured suffix
+#[derive(Debug, Default)]
+pub struct DomainCategorizer {
+    suffixes: HashMap<String, String>,
+}
+
+impl DomainCategorizer {
+    /// Load categories from a file, see [`Self::parse`] for the format
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    /// Parse `category=suffix,suffix; category=suffix` into a categorizer
+    ///
+    /// Newlines may be used in place of `;`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut suffixes = HashMap::default();
+        for entry in spec.split([';', '\n']).map(str::trim) {
+            if entry.is_empty() {
+                continue;
+            }
+            let (category, domains) = entry.split_once('=').ok_or_else(|| {
+                PiholeError::Config(format!("domain category `{entry}` has no `=`"))
+            })?;
+            for suffix in domains.split(',').map(str::trim) {
+                if !suffix.is_empty() {
+                    suffixes.insert(
+                        suffix.trim_matches('.').to_ascii_lowercase(),
+                        category.trim().to_string(),
+                    );
+                }
+            }
+        }
+        Ok(Self { suffixes })
+    }
+
+    /// Category of `domain`, or [`UNCATEGORIZED`]
+    ///
+    /// `www.youtube.com` matches a `youtube.com` suffix, `notyoutube.com` does not.
+    pub fn categorize(&self, domain: &str) -> &str {
+        let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+        let mut rest = domain.as_str();
+        loop {
+            if let Some(category) = self.suffixes.get(rest) {
+                return category;
+            }
+            match rest.split_once('.') {
+                Some((_, parent)) => rest = parent,
+                None => return UNCATEGORIZED,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_categorize_by_suffix() {
+        let categorizer = DomainCategorizer::parse(
+            "social=facebook.com,twitter.com; streaming=netflix.com, youtube.com",
+        )
+        .unwrap();
+
+        assert_eq!(categorizer.categorize("facebook.com"), "social");
+        assert_eq!(categorizer.categorize("www.YouTube.com."), "streaming");
+        assert_eq!(categorizer.categorize("notyoutube.com"), UNCATEGORIZED);
+        assert_eq!(categorizer.categorize("example.org"), UNCATEGORIZED);
+        assert!(DomainCategorizer::parse("social").is_err());
+    }
+}
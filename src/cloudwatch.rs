@@ -0,0 +1,113 @@
+use aws_sdk_cloudwatch::{
+    Client,
+    types::{MetricDatum, StandardUnit},
+};
+use tracing::{debug, warn};
+
+use crate::metrics::{CategoryLabels, PiholeMetrics};
+
+/// `pihole_query_count` categories sent to CloudWatch, with the metric name used there
+const CLOUDWATCH_QUERY_COUNTS: &[(&str, &str)] = &[
+    ("total", "QueriesTotal"),
+    ("blocked", "QueriesBlocked"),
+    ("forwarded", "QueriesForwarded"),
+    ("cached", "QueriesCached"),
+];
+
+/// Sends the main scalar metrics to AWS CloudWatch with `PutMetricData`
+#[derive(Debug)]
+pub struct CloudWatchEmitter {
+    client: Client,
+    namespace: String,
+}
+
+impl CloudWatchEmitter {
+    /// Create an emitter publishing under `namespace`
+    ///
+    /// Region and credentials come from the standard AWS environment,
+    /// profile and instance metadata chain.
+    pub async fn new(namespace: &str) -> Self {
+        let config = aws_config::load_from_env().await;
+        Self {
+            client: Client::new(&config),
+            namespace: namespace.to_string(),
+        }
+    }
+
+    /// Publish the query counts and blocklist size from `metrics`
+    ///
+    /// Values are read from the metric fields, so they do not depend on the
+    /// labels added on exposition. Failures are only logged, like the DogStatsD output.
+    pub async fn emit(&self, metrics: &PiholeMetrics) {
+        let data = metric_data(metrics);
+        if data.is_empty() {
+            return;
+        }
+        let sent = data.len();
+        match self
+            .client
+            .put_metric_data()
+            .namespace(&self.namespace)
+            .set_metric_data(Some(data))
+            .send()
+            .await
+        {
+            Ok(_) => debug!("Sent {} metrics to CloudWatch", sent),
+            Err(e) => warn!("Failed to send CloudWatch metrics: {}", e),
+        }
+    }
+}
+
+/// Build a datum for each [`CLOUDWATCH_QUERY_COUNTS`] category set in `metrics`,
+/// and for the blocklist size
+fn metric_data(metrics: &PiholeMetrics) -> Vec<MetricDatum> {
+    CLOUDWATCH_QUERY_COUNTS
+        .iter()
+        .filter_map(|(category, name)| {
+            let value = metrics.query_count.get(&CategoryLabels {
+                category: category.to_string(),
+            })?;
+            Some((*name, value))
+        })
+        .chain([("DomainsBeingBlocked", metrics.domains_being_blocked.get())])
+        .map(|(name, value)| {
+            MetricDatum::builder()
+                .metric_name(name)
+                .value(value as f64)
+                .unit(StandardUnit::Count)
+                .build()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use prometheus_client::registry::Registry;
+
+    use super::*;
+
+    #[test]
+    fn test_metric_data() {
+        let metrics = PiholeMetrics::new();
+        // Labels added to every series on exposition must not hide the values
+        let mut registry = Registry::with_labels(
+            [(Cow::Borrowed("environment"), Cow::Borrowed("prod"))].into_iter(),
+        );
+        metrics.register(&mut registry);
+        metrics.query_count.set(
+            &CategoryLabels {
+                category: "total".to_string(),
+            },
+            7497,
+        );
+        metrics.domains_being_blocked.set(104584);
+
+        let data = metric_data(&metrics);
+        let names: Vec<_> = data.iter().filter_map(|d| d.metric_name()).collect();
+        assert_eq!(names, ["QueriesTotal", "DomainsBeingBlocked"]);
+        assert_eq!(data[0].value(), Some(7497.0));
+        assert_eq!(data[1].value(), Some(104584.0));
+    }
+}
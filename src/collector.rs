@@ -1,229 +1,1547 @@
 use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    fmt::{self, Write},
+    net::{IpAddr, SocketAddr},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use ahash::{HashMap, HashMapExt};
+use ahash::{HashMap, HashMapExt, HashSet, HashSetExt};
 use chrono::Utc;
-use prometheus_client::{encoding::text::encode, registry::Registry};
-use reqwest::Client;
+use hickory_resolver::{
+    Resolver, TokioResolver,
+    config::{ConnectionConfig, NameServerConfig, ResolverConfig},
+    net::runtime::TokioRuntimeProvider,
+    proto::rr::RData,
+};
+use prometheus_client::{
+    encoding::text::{encode_eof, encode_registry},
+    registry::Registry,
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use regex::Regex;
+use reqwest::{
+    Client, NoProxy, Proxy, StatusCode,
+    header::{ETAG, HeaderMap, HeaderName, HeaderValue, IF_NONE_MATCH, RETRY_AFTER},
+};
 use secrecy::{ExposeSecret, SecretString};
+use serde::de::DeserializeOwned;
 use serde_json::Value;
+use tokio::sync::{Semaphore, watch};
+use tracing::{Instrument, Span, debug, field, info, info_span, warn};
 
+#[cfg(feature = "cloudwatch")]
+use crate::cloudwatch::CloudWatchEmitter;
 use crate::{
-    Result,
-    api::{AuthRequest, AuthResponse, QueriesResponse, StatsResponse, UpstreamsResponse},
+    Args, ArgsPublic, PiholeError, Result,
+    api::{
+        AuthRequest, AuthResponse, DatabaseInfoResponse, DhcpConfigResponse, DhcpStatusResponse,
+        DnsConfigResponse, DomainsResponse, FtlInfoResponse, GroupsResponse,
+        HistoricalDataResponse, ListsResponse, QueriesResponse, StatsResponse, SystemInfoResponse,
+        UpstreamsResponse, VersionResponse, reply_rcode,
+    },
+    asn::{AsnInfo, AsnLookup},
+    categorizer::DomainCategorizer,
+    disk,
     metrics::{
-        CategoryLabels, ClientLabels, PiholeMetrics, QueryStatusLabels, QueryTypeLabels,
-        ReplyTypeLabels, UpstreamCountLabels, UpstreamLabels,
+        ApiEndpointLabels, ApiVersionLabels, AsnLabels, AuthMethodLabels, BlockReasonLabels,
+        BlocklistLabels, CategoryLabels, ClientLabels, ClientTypeLabels, DescribedRegistry,
+        DhcpLeaseLabels, ExporterSelfMetrics, GroupLabels, HistoricalLabels, InFlightLabels,
+        IpVersionLabels, PiholeMetrics, QueryStatusLabels, QueryTypeLabels, RcodeLabels,
+        ReplyTypeLabels, UpstreamCacheLabels, UpstreamCountLabels, UpstreamLabels, WindowLabels,
+        WindowMetrics, builtin_metric_descriptions, load_metric_descriptions, set_if_changed,
     },
+    statsd::StatsdEmitter,
+    tls,
 };
 
+/// Maximum number of queries requested per window
+const QUERY_WINDOW_LENGTH: usize = 1_000_000;
+
+/// Wait before retrying a rate-limited login when Pi-hole gives no Retry-After
+const DEFAULT_AUTH_RETRY_AFTER: Duration = Duration::from_secs(60);
+
+/// Number of API call durations averaged per endpoint
+const API_TIMING_SAMPLES: usize = 10;
+
+/// API paths whose responses are cached with their ETag
+const ETAG_CACHED_PATHS: [&str; 3] = ["stats/summary", "groups", "lists"];
+
+/// API path prefixes whose responses are cached with their ETag, alongside [`ETAG_CACHED_PATHS`]
+const ETAG_CACHED_PREFIXES: [&str; 2] = ["config/", "info/"];
+
+/// Series that change on every update and do not count as a change for /metrics/changes
+const SELF_METRIC_PREFIXES: [&str; 5] = [
+    "pihole_exporter_",
+    "pihole_api_response_time",
+    "pihole_stale_scrape",
+    "pihole_auth_session_age",
+    "pihole_gravity_age",
+];
+
+/// Reply type FTL uses for non-existent domains
+const NXDOMAIN_REPLY: &str = "NXDOMAIN";
+
+/// Query statuses FTL uses for answers served from its cache
+const CACHED_STATUSES: &[&str] = &["CACHE", "CACHE_STALE"];
+
+/// Query statuses FTL uses for answers fetched from an upstream
+const FORWARDED_STATUSES: &[&str] = &["FORWARDED", "RETRIED", "RETRIED_DNSSEC"];
+
+/// Status of queries FTL answers itself, e.g. for iCloud Private Relay or the Firefox DoH canary
+const SPECIAL_DOMAIN_STATUS: &str = "SPECIAL_DOMAIN";
+
+/// Fields (as JSON pointers) the exporter reads from each API endpoint
+const API_SCHEMA: &[(&str, &[&str])] = &[
+    (
+        "stats/summary",
+        &[
+            "/queries/total",
+            "/queries/blocked",
+            "/queries/unique_domains",
+            "/queries/forwarded",
+            "/queries/cached",
+            "/queries/types",
+            "/queries/status",
+            "/queries/replies",
+            "/clients/active",
+            "/clients/total",
+            "/gravity/domains_being_blocked",
+        ],
+    ),
+    ("stats/upstreams", &["/upstreams"]),
+    ("groups", &["/groups"]),
+    ("lists", &["/lists"]),
+    ("domains", &["/domains"]),
+    ("info/version", &["/version/ftl/local/version"]),
+];
+
+/// Pi-hole API major versions the exporter has been tested against
+const TESTED_API_VERSION_RANGE: RangeInclusive<u64> = 6..=6;
+
+/// Long-term history periods as (label, days), longest last
+const HISTORICAL_PERIODS: [(&str, i64); 2] = [("7d", 7), ("30d", 30)];
+
+/// Renames or merges Pi-hole query status values before they become labels
+///
+/// Statuses without a mapping pass through unchanged.
+#[derive(Debug, Default)]
+pub struct StatusMapper {
+    mappings: HashMap<String, String>,
+}
+
+impl StatusMapper {
+    /// Create a mapper from `(from, to)` pairs
+    pub fn new(mappings: impl IntoIterator<Item = (String, String)>) -> Self {
+        Self {
+            mappings: mappings.into_iter().collect(),
+        }
+    }
+
+    /// Map a raw status to the value used in metric labels
+    pub fn map<'a>(&'a self, status: &'a str) -> &'a str {
+        self.mappings.get(status).map_or(status, String::as_str)
+    }
+}
+
+/// Collapses query statuses outside an include list into `other`
+///
+/// An empty include list keeps every status.
+#[derive(Debug, Default)]
+pub struct StatusFilter {
+    include: HashSet<String>,
+}
+
+impl StatusFilter {
+    /// Label value used for statuses that are not included
+    pub const OTHER: &'static str = "other";
+
+    /// Create a filter keeping only `include`, or everything when empty
+    pub fn new(include: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            include: include.into_iter().collect(),
+        }
+    }
+
+    /// Map a status to itself if included, otherwise to [`Self::OTHER`]
+    pub fn filter_status<'a>(&self, status: &'a str) -> &'a str {
+        if self.include.is_empty() || self.include.contains(status) {
+            status
+        } else {
+            Self::OTHER
+        }
+    }
+}
+
+/// Recent API call durations per endpoint, for the rolling average
+#[derive(Debug, Default)]
+struct ApiTimingState {
+    samples: HashMap<String, VecDeque<f64>>,
+}
+
+impl ApiTimingState {
+    /// Record a duration for `endpoint` and return its rolling average
+    fn record(&mut self, endpoint: &str, seconds: f64) -> f64 {
+        let samples = self.samples.entry(endpoint.to_string()).or_default();
+        samples.push_back(seconds);
+        if samples.len() > API_TIMING_SAMPLES {
+            samples.pop_front();
+        }
+        samples.iter().sum::<f64>() / samples.len() as f64
+    }
+}
+
+/// Current metric values keyed by series (name and labels, as exposed)
+pub type MetricsSnapshot = HashMap<String, f64>;
+
+/// When the collector calls the Pi-hole API
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScrapeMode {
+    /// Update on every scrape, before the metrics are encoded
+    OnDemand,
+    /// Update on a fixed period, scrapes serve the latest values
+    Background(Duration),
+}
+
+impl From<&Args> for ScrapeMode {
+    fn from(args: &Args) -> Self {
+        match args.scrape_interval_secs {
+            Some(secs) => Self::Background(Duration::from_secs(secs)),
+            None => Self::OnDemand,
+        }
+    }
+}
+
+/// Random delay before the first background update, below `jitter` in whole seconds
+///
+/// Spreads the updates of exporters started together, see `--scrape-jitter-secs`.
+pub fn initial_scrape_delay(jitter: Duration) -> Duration {
+    match jitter.as_secs() {
+        0 => Duration::ZERO,
+        secs => Duration::from_secs(rand::random::<u64>() % secs),
+    }
+}
+
+/// Time between two background updates, `period` shifted by a random amount within ±`jitter`/2
+pub fn next_scrape_delay(period: Duration, jitter: Duration) -> Duration {
+    let half = (jitter.as_millis() / 2) as i64;
+    if half == 0 {
+        return period;
+    }
+    let millis = period.as_millis() as i64 + rand::random_range(-half..=half);
+    Duration::from_millis(millis.max(0) as u64)
+}
+
 /// Pi-hole collector that fetches metrics from Pi-hole API and updates Prometheus metrics
 #[derive(Debug)]
 pub struct PiholeCollector {
+    pub host: String,
+    /// Value of the `instance` label on every series, if set
+    pub instance_name: Option<String>,
     pub base: String,
+    pub scrape_mode: ScrapeMode,
+    /// Path of the API below `base`, e.g. `/api`
+    pub api_path_prefix: String,
     pub client: Client,
-    pub sid: Option<String>,
+    /// Session ID sent with each API call, replaced when the session is renewed
+    sid: Mutex<Option<String>>,
+    password: Option<SecretString>,
+    /// Pi-hole session timeout, sessions are renewed at 80% of it
+    pub session_timeout: Duration,
     pub metrics: PiholeMetrics,
-    pub registry: Arc<Mutex<Registry>>,
+    pub exporter_metrics: ExporterSelfMetrics,
+    /// One registry per metric family, see [`DescribedRegistry::per_family`]
+    pub registry: Arc<Mutex<Vec<Registry>>>,
+    pub enable_historical_metrics: bool,
+    pub enable_dhcp_metrics: bool,
+    pub disable_window_metrics: bool,
+    pub per_client_type_metrics: bool,
+    /// Report RFC 1918 client addresses as their /24 subnet
+    pub strip_private_ips: bool,
+    /// Busiest clients per window given per-client type metrics, 0 for all
+    pub top_clients_limit: usize,
+    pub status_mapper: StatusMapper,
+    pub status_filter: StatusFilter,
+    /// Encoded lines with a label set matching this are dropped
+    pub metrics_filter: Option<Regex>,
+    /// Label keys renamed in the encoded output, as from -> to
+    pub label_renames: HashMap<String, String>,
+    /// Consecutive connection failures before the watchdog restarts FTL, if enabled
+    pub watchdog_threshold: Option<u32>,
+    /// How long an upstream can go unreported before its series is zeroed, if enabled
+    pub max_upstream_age: Option<Duration>,
+    /// Sends metric values to DogStatsD after each successful update, if configured
+    pub statsd: Option<StatsdEmitter>,
+    /// Sends the main metrics to CloudWatch after each successful update, if configured
+    #[cfg(feature = "cloudwatch")]
+    pub cloudwatch: Option<CloudWatchEmitter>,
+    /// Resolver used for reverse DNS lookups
+    pub resolver: TokioResolver,
+    /// How long reverse DNS names for upstreams are cached
+    pub upstream_name_ttl: Duration,
+    /// How long /metrics/changes waits for a change
+    pub long_poll_timeout: Duration,
+    /// Limits how many scrapes run `update_metrics` at once
+    pub scrape_semaphore: Arc<Semaphore>,
+    /// Fraction of queries counted in window metrics
+    pub query_sample_rate: f64,
+    pub asn: Option<AsnLookup>,
+    pub categorizer: Option<DomainCategorizer>,
+    /// Path whose filesystem usage is reported
+    pub monitor_disk_path: Option<PathBuf>,
+    /// Notifies subscribers when a successful update changes any metric value
+    changes: watch::Sender<MetricsSnapshot>,
+    /// Total and answered query counts from the previous scrape
+    previous_query_totals: Mutex<Option<(u64, u64)>>,
+    /// Gravity last update timestamp from the previous scrape
+    previous_gravity_update: Mutex<Option<u64>>,
+    /// Consecutive updates that failed to connect to Pi-hole
+    connect_failures: Mutex<u32>,
+    /// When the current session was obtained from `get_sid`
+    session_started: Mutex<Option<Instant>>,
+    /// When `update_metrics` last succeeded
+    last_update: Mutex<Option<Instant>>,
+    /// Whether the most recent `update_metrics` failed, leaving the metrics stale
+    last_update_failed: Mutex<bool>,
+    /// Most recent custom domain modification time seen, in Unix seconds
+    last_audit_timestamp: Mutex<Option<u64>>,
+    /// When each upstream label set was last reported by Pi-hole
+    upstream_last_seen: Mutex<HashMap<UpstreamLabels, Instant>>,
+    /// Reverse DNS names keyed by upstream IP, as (name, resolved at)
+    upstream_names: Mutex<HashMap<String, (String, Instant)>>,
+    /// Recent API call durations per endpoint
+    api_timing: Mutex<ApiTimingState>,
+    /// Cached responses keyed by API path, as (etag, body), see [`is_etag_cacheable`]
+    etag_cache: Mutex<HashMap<String, (String, Value)>>,
 }
 
 impl PiholeCollector {
     /// Create a new PiholeCollector instance
-    pub async fn new(host: String, tls: bool, key: Option<SecretString>) -> Result<Self> {
-        let client = Client::builder()
+    pub async fn new(args: &Args) -> Result<Self> {
+        let base = format!(
+            "{}://{}",
+            if args.tls { "https" } else { "http" },
+            args.pihole
+        );
+        #[cfg_attr(not(feature = "cloudwatch"), allow(unused_mut))]
+        let mut collector = Self::with_base(args, base)?;
+        #[cfg(feature = "cloudwatch")]
+        if let Some(namespace) = &args.cloudwatch_namespace {
+            collector.cloudwatch = Some(CloudWatchEmitter::new(namespace).await);
+        }
+
+        collector.login().await?;
+        // Pi-hole may accept the login without a session when it has no password set
+        let method = if collector.sid().is_some() {
+            "password"
+        } else {
+            "none"
+        };
+        collector.metrics.auth_method_info.set(
+            &AuthMethodLabels {
+                method: method.to_string(),
+            },
+            1,
+        );
+
+        collector.prefetch_and_validate().await?;
+        if let Err(e) = collector.detect_api_version().await {
+            warn!("Failed to detect the Pi-hole API version: {}", e);
+        }
+
+        Ok(collector)
+    }
+
+    /// Create an unauthenticated collector for a mock Pi-hole at `base_url`
+    #[cfg(test)]
+    pub fn from_mock_server(base_url: String) -> Self {
+        use clap::Parser;
+
+        let args = Args::parse_from(["pihole-exporter"]);
+        Self::with_base(&args, base_url).expect("building collector")
+    }
+
+    /// Build an unauthenticated collector talking to the API at `base`
+    fn with_base(args: &Args, base: String) -> Result<Self> {
+        validate_api_path_prefix(&args.api_path_prefix)?;
+
+        // Validated when the arguments were parsed
+        let default_headers: HeaderMap = args
+            .request_headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_bytes(name.as_bytes()).expect("valid header name"),
+                    HeaderValue::from_str(value).expect("valid header value"),
+                )
+            })
+            .collect();
+        let mut client = Client::builder()
             .danger_accept_invalid_certs(true)
             .timeout(Duration::from_secs(30))
-            .build()?;
+            .user_agent(&args.user_agent)
+            .default_headers(default_headers);
 
-        let base = format!("{}://{host}", if tls { "https" } else { "http" });
+        // Without an explicit proxy reqwest falls back to the proxy environment variables
+        if let Some(proxy) = &args.http_proxy {
+            let no_proxy = NoProxy::from_string(&args.no_proxy.join(","));
+            let proxy = Proxy::all(proxy)
+                .map_err(|e| PiholeError::from(e).context("parsing --http-proxy"))?;
+            client = client.proxy(proxy.no_proxy(no_proxy));
+        }
+        let client = client
+            .build()
+            .map_err(|e| PiholeError::from(e).context("building HTTP client"))?;
 
-        let sid = if let Some(key) = key {
-            Some(Self::get_sid(&client, &base, key.expose_secret()).await?)
-        } else {
-            None
-        };
+        let metrics_filter = args
+            .metrics_filter_regex
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| PiholeError::from(e).context("parsing --metrics-filter-regex"))?;
 
-        let metrics = PiholeMetrics::new();
-        let mut registry = Registry::default();
-        metrics.register(&mut registry);
+        let metrics = PiholeMetrics::with_config(args.max_label_values, &args.window_intervals);
+        let mut descriptions = builtin_metric_descriptions(&args.metric_language)
+            .map_err(|e| e.context(format!("loading {} help text", args.metric_language)))?;
+        if let Some(path) = &args.metric_descriptions_file {
+            descriptions.extend(
+                load_metric_descriptions(path)
+                    .map_err(|e| e.context(format!("loading {}", path.display())))?,
+            );
+        }
+        let base_labels = args
+            .base_labels
+            .iter()
+            .map(|(key, value)| (Cow::Owned(key.clone()), Cow::Owned(value.clone())))
+            .chain(
+                args.instance_name
+                    .iter()
+                    .map(|name| (Cow::Borrowed("instance"), Cow::Owned(name.clone()))),
+            )
+            .collect::<Vec<_>>();
+        let mut registries = Vec::new();
+        let mut described =
+            DescribedRegistry::per_family(&mut registries, &base_labels, &descriptions);
+        metrics.register_described(&mut described);
+        let exporter_metrics = ExporterSelfMetrics::new();
+        exporter_metrics.register_described(&mut described);
+        exporter_metrics
+            .config_hash
+            .set(ArgsPublic::from(args).config_hash());
+        metrics.query_sample_rate.set(args.query_sample_rate);
 
         Ok(Self {
+            host: args.pihole.clone(),
+            instance_name: args.instance_name.clone(),
+            scrape_mode: ScrapeMode::from(args),
             base,
+            api_path_prefix: args.api_path_prefix.clone(),
             client,
-            sid,
+            sid: Mutex::new(None),
+            password: args.password.clone(),
+            session_timeout: Duration::from_secs(args.session_timeout_secs),
             metrics,
-            registry: Arc::new(Mutex::new(registry)),
+            exporter_metrics,
+            registry: Arc::new(Mutex::new(registries)),
+            enable_historical_metrics: args.enable_historical_metrics,
+            enable_dhcp_metrics: args.enable_dhcp_metrics,
+            disable_window_metrics: args.disable_window_metrics,
+            per_client_type_metrics: args.per_client_type_metrics,
+            strip_private_ips: args.strip_private_ips,
+            top_clients_limit: args.top_clients_limit,
+            status_mapper: StatusMapper::new(args.status_map.iter().cloned()),
+            status_filter: StatusFilter::new(args.status_include_list.iter().cloned()),
+            metrics_filter,
+            label_renames: args.rename_labels.iter().cloned().collect(),
+            watchdog_threshold: args
+                .watchdog_mode
+                .then_some(args.watchdog_restart_threshold),
+            max_upstream_age: (args.max_upstream_age_secs > 0)
+                .then(|| Duration::from_secs(args.max_upstream_age_secs)),
+            statsd: args
+                .statsd_host
+                .as_deref()
+                .map(StatsdEmitter::new)
+                .transpose()
+                .map_err(|e| e.context("connecting to --statsd-host"))?,
+            #[cfg(feature = "cloudwatch")]
+            cloudwatch: None,
+            resolver: Self::build_resolver(args.resolver_upstream)?,
+            upstream_name_ttl: Duration::from_secs(args.upstream_name_cache_ttl_secs),
+            long_poll_timeout: Duration::from_secs(args.long_poll_timeout_secs),
+            scrape_semaphore: Arc::new(Semaphore::new(args.max_concurrent_scrapes as usize)),
+            query_sample_rate: args.query_sample_rate,
+            asn: args
+                .asn_database
+                .as_deref()
+                .map(AsnLookup::open)
+                .transpose()
+                .map_err(|e| e.context("loading --asn-database"))?,
+            monitor_disk_path: args.monitor_disk_path.clone(),
+            categorizer: args
+                .domain_categories_file
+                .as_deref()
+                .map(DomainCategorizer::load)
+                .transpose()
+                .map_err(|e| e.context("loading --domain-categories-file"))?,
+            changes: watch::Sender::new(MetricsSnapshot::new()),
+            previous_query_totals: Mutex::new(None),
+            previous_gravity_update: Mutex::new(None),
+            connect_failures: Mutex::new(0),
+            session_started: Mutex::new(None),
+            last_update: Mutex::new(None),
+            last_update_failed: Mutex::new(false),
+            last_audit_timestamp: Mutex::new(None),
+            upstream_last_seen: Mutex::new(HashMap::new()),
+            upstream_names: Mutex::new(HashMap::new()),
+            api_timing: Mutex::new(ApiTimingState::default()),
+            etag_cache: Mutex::new(HashMap::new()),
         })
     }
 
+    /// Full URL of an API endpoint
+    fn api_url(&self, api_path: &str) -> String {
+        format!("{}{}/{}", self.base, self.api_path_prefix, api_path)
+    }
+
+    /// Label value for a raw query status, after mapping and filtering
+    fn status_label<'a>(&'a self, status: &'a str) -> &'a str {
+        self.status_filter
+            .filter_status(self.status_mapper.map(status))
+    }
+
+    /// Check that Pi-hole answers `stats/summary` in the shape we expect
+    ///
+    /// Surfaces connection problems and API changes at startup instead of on the first scrape.
+    async fn prefetch_and_validate(&self) -> Result<()> {
+        let summary_json = self.get_api_call("stats/summary").await?;
+        serde_json::from_value::<StatsResponse>(summary_json)
+            .map_err(|e| PiholeError::InvalidResponse(format!("stats/summary: {e}")))?;
+        Ok(())
+    }
+
+    /// Record the Pi-hole API version, warning when it is outside the tested range
+    async fn detect_api_version(&self) -> Result<()> {
+        let version: VersionResponse = self.get_api("info/version").await?;
+        let raw = version.version.ftl.local.version;
+        let (major, minor, patch) = parse_version(&raw).ok_or_else(|| {
+            PiholeError::InvalidResponse(format!("info/version: unparseable version {raw:?}"))
+        })?;
+
+        info!("Detected Pi-hole API version {}.{}.{}", major, minor, patch);
+        if !TESTED_API_VERSION_RANGE.contains(&major) {
+            warn!(
+                "Pi-hole API version {} is outside the tested range {:?}, metrics may be missing or wrong",
+                raw, TESTED_API_VERSION_RANGE
+            );
+        }
+
+        set_if_changed(&self.metrics.api_version_detected, major as i64);
+        self.metrics.api_version_info.set(
+            &ApiVersionLabels {
+                major: major.to_string(),
+                minor: minor.to_string(),
+                patch: patch.to_string(),
+            },
+            1,
+        );
+        Ok(())
+    }
+
+    /// Build a resolver for `upstream`, or from the system configuration when `None`
+    fn build_resolver(upstream: Option<SocketAddr>) -> Result<TokioResolver> {
+        let builder = match upstream {
+            Some(upstream) => {
+                let connections = [ConnectionConfig::udp(), ConnectionConfig::tcp()]
+                    .into_iter()
+                    .map(|mut connection| {
+                        connection.port = upstream.port();
+                        connection
+                    })
+                    .collect();
+                let config = ResolverConfig::from_name_servers(vec![NameServerConfig::new(
+                    upstream.ip(),
+                    true,
+                    connections,
+                )]);
+                Resolver::builder_with_config(config, TokioRuntimeProvider::default())
+            }
+            None => Resolver::builder_tokio()
+                .map_err(|e| PiholeError::Config(format!("system DNS configuration: {e}")))?,
+        };
+        builder
+            .build()
+            .map_err(|e| PiholeError::Config(format!("DNS resolver: {e}")))
+    }
+
+    /// Current session ID, `None` when unauthenticated
+    pub fn sid(&self) -> Option<String> {
+        self.sid.lock().unwrap().clone()
+    }
+
+    /// Log in with the configured password, if any, replacing the session ID
+    async fn login(&self) -> Result<()> {
+        let Some(key) = &self.password else {
+            return Ok(());
+        };
+        let sid = Self::get_sid(&self.client, &self.api_url("auth"), key.expose_secret())
+            .await
+            .map_err(|e| e.context(format!("logging in to {}", self.base)))?;
+        *self.sid.lock().unwrap() = sid;
+        *self.session_started.lock().unwrap() = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Log in again when the session is older than 80% of the session timeout
+    ///
+    /// Renewing ahead of expiry keeps scrapes from waiting on a login.
+    /// Returns whether the session was renewed.
+    pub async fn refresh_auth_if_expiring(&self) -> Result<bool> {
+        let Some(started) = *self.session_started.lock().unwrap() else {
+            return Ok(false);
+        };
+        if self.sid().is_none() || started.elapsed() < self.session_timeout * 4 / 5 {
+            return Ok(false);
+        }
+        self.login().await?;
+        Ok(true)
+    }
+
     /// Authenticate with Pi-hole and get session ID
-    async fn get_sid(client: &Client, base: &str, key: &str) -> Result<String> {
-        let auth_url = format!("{base}/api/auth");
+    ///
+    /// Returns `None` when Pi-hole accepts the login without issuing a session,
+    /// which happens when no password is configured on the Pi-hole.
+    async fn get_sid(client: &Client, auth_url: &str, key: &str) -> Result<Option<String>> {
         let auth_request = AuthRequest {
             password: key.to_string(),
         };
 
         let response = client
-            .post(&auth_url)
+            .post(auth_url)
             .header("accept", "application/json")
             .header("content-type", "application/json")
             .json(&auth_request)
             .send()
             .await?;
 
-        let auth_response: AuthResponse = response.json().await?;
-        Ok(auth_response.session.sid)
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok())
+            .map_or(DEFAULT_AUTH_RETRY_AFTER, Duration::from_secs);
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            return Err(PiholeError::AuthRateLimited(retry_after));
+        }
+
+        let session = response.json::<AuthResponse>().await?.session;
+        if session
+            .message
+            .as_deref()
+            .is_some_and(|message| message.to_ascii_lowercase().contains("rate"))
+        {
+            return Err(PiholeError::AuthRateLimited(retry_after));
+        }
+        if !session.valid {
+            return Err(PiholeError::Auth(
+                "session invalid: wrong password".to_string(),
+            ));
+        }
+
+        Ok(session.sid)
     }
 
     /// Make an API call to Pi-hole
-    async fn get_api_call(&self, api_path: &str) -> Result<Value> {
-        let url = format!("{}/api/{}", self.base, api_path);
+    pub(crate) async fn get_api_call(&self, api_path: &str) -> Result<Value> {
+        let start = Instant::now();
+        let result = self.get_adhoc_api_call(api_path).await;
+        self.record_api_timing(api_path, start.elapsed());
+        result
+    }
+
+    /// Make an API call on behalf of a user request, e.g. to /query or /check
+    ///
+    /// Unlike [`Self::get_api_call`] it is left out of the per-endpoint timing metrics,
+    /// which track the calls made for collection.
+    pub(crate) async fn get_adhoc_api_call(&self, api_path: &str) -> Result<Value> {
+        let span = info_span!(
+            "pihole_api_call",
+            pihole.api.path = api_path,
+            http.status_code = field::Empty,
+            http.response_size = field::Empty,
+        );
+        self.fetch_api_call(api_path)
+            .instrument(span)
+            .await
+            .map_err(|e| e.context(format!("api call to {}", api_endpoint(api_path))))
+    }
+
+    /// Make an API call to Pi-hole and parse the response as `T`
+    async fn get_api<T: DeserializeOwned>(&self, api_path: &str) -> Result<T> {
+        let json = self.get_api_call(api_path).await?;
+        serde_json::from_value(json).map_err(|e| {
+            PiholeError::from(e).context(format!("parsing {} response", api_endpoint(api_path)))
+        })
+    }
+
+    /// Update the last and rolling average response time for the endpoint of `api_path`
+    fn record_api_timing(&self, api_path: &str, elapsed: Duration) {
+        let endpoint = api_endpoint(api_path);
+        let seconds = elapsed.as_secs_f64();
+        let average = self.api_timing.lock().unwrap().record(endpoint, seconds);
+
+        let labels = ApiEndpointLabels {
+            endpoint: endpoint.to_string(),
+        };
+        self.metrics
+            .api_response_time
+            .get_or_create(&labels)
+            .set(seconds);
+        self.metrics
+            .api_response_time_avg
+            .get_or_create(&labels)
+            .set(average);
+    }
+
+    /// Perform the request for [`Self::get_api_call`] inside its span
+    async fn fetch_api_call(&self, api_path: &str) -> Result<Value> {
+        let url = self.api_url(api_path);
         let mut request = self.client.get(&url).header("accept", "application/json");
 
-        if let Some(ref sid) = self.sid {
+        if let Some(sid) = self.sid() {
             request = request.header("sid", sid);
         }
 
+        let cacheable = is_etag_cacheable(api_path);
+        let cached_etag = self
+            .etag_cache
+            .lock()
+            .unwrap()
+            .get(api_path)
+            .filter(|_| cacheable)
+            .map(|(etag, _)| etag.clone());
+        if let Some(etag) = cached_etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+
         let response = request.send().await?;
-        let json: Value = response.json().await?;
+        let span = Span::current();
+        span.record("http.status_code", response.status().as_u16());
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            // The cache can be cleared, or the server may ignore the missing If-None-Match
+            return self
+                .etag_cache
+                .lock()
+                .unwrap()
+                .get(api_path)
+                .map(|(_, body)| body.clone())
+                .ok_or_else(|| {
+                    PiholeError::InvalidResponse(format!(
+                        "{api_path} returned 304 Not Modified with no cached response"
+                    ))
+                });
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_string);
+        let body = response.bytes().await?;
+        span.record("http.response_size", body.len());
+        let json: Value = serde_json::from_slice(&body)?;
+
+        if let Some(etag) = etag.filter(|_| cacheable) {
+            self.etag_cache
+                .lock()
+                .unwrap()
+                .insert(api_path.to_string(), (etag, json.clone()));
+        }
+
         Ok(json)
     }
 
     /// Update all metrics by fetching data from Pi-hole API
     pub async fn update_metrics(&self) -> Result<()> {
+        let result = self.collect_metrics().await;
+        if self.watchdog_threshold.is_some() {
+            self.watchdog(&result).await;
+        }
+        set_if_changed(
+            &self.metrics.last_error_type,
+            result.as_ref().err().map_or(0, PiholeError::error_type),
+        );
+        *self.last_update_failed.lock().unwrap() = result.is_err();
+        if result.is_ok() {
+            *self.last_update.lock().unwrap() = Some(Instant::now());
+            set_if_changed(&self.metrics.stale_scrape_age, 0);
+            if let Some(statsd) = &self.statsd {
+                match self.encode_metrics() {
+                    Ok(encoded) => statsd.emit(&encoded),
+                    Err(e) => warn!("Failed to encode metrics for DogStatsD: {}", e),
+                }
+            }
+            #[cfg(feature = "cloudwatch")]
+            if let Some(cloudwatch) = &self.cloudwatch {
+                cloudwatch.emit(&self.metrics).await;
+            }
+        }
+        result
+    }
+
+    /// Count a config change when gravity's last update timestamp differs from the last scrape
+    ///
+    /// The first timestamp seen only sets the baseline, and 0 (not reported) is ignored.
+    fn record_gravity_update(&self, last_update: u64) {
+        if last_update == 0 {
+            return;
+        }
+        let previous = self
+            .previous_gravity_update
+            .lock()
+            .unwrap()
+            .replace(last_update);
+        if previous.is_some_and(|previous| previous != last_update) {
+            self.metrics.config_changed.inc();
+        }
+    }
+
+    /// Time since the last successful update, or `None` if none has succeeded yet
+    pub fn last_update_age(&self) -> Option<Duration> {
+        self.last_update.lock().unwrap().map(|at| at.elapsed())
+    }
+
+    /// Record a stale scrape if the latest background update failed, see [`Self::record_stale_scrape`]
+    ///
+    /// Returns the age of the served data when it is stale.
+    pub fn record_scrape_if_stale(&self) -> Option<Duration> {
+        if *self.last_update_failed.lock().unwrap() {
+            self.record_stale_scrape()
+        } else {
+            None
+        }
+    }
+
+    /// Record a scrape served from the last successful update after `update_metrics` failed
+    ///
+    /// Returns the age of that data, or `None` if no update has succeeded yet.
+    pub fn record_stale_scrape(&self) -> Option<Duration> {
+        let age = (*self.last_update.lock().unwrap())?.elapsed();
+        self.metrics.stale_scrapes.inc();
+        set_if_changed(&self.metrics.stale_scrape_age, age.as_secs() as i64);
+        Some(age)
+    }
+
+    /// Track connection failures and restart FTL once they reach the watchdog threshold
+    ///
+    /// FTL serves the API itself, so the restart only gets through when something
+    /// still answers on the API address, e.g. a reverse proxy or a recovering FTL.
+    async fn watchdog(&self, result: &Result<()>) {
+        let refused = matches!(
+            result.as_ref().map_err(PiholeError::root),
+            Err(PiholeError::Http(e)) if e.is_connect()
+        );
+        let failures = {
+            let mut failures = self.connect_failures.lock().unwrap();
+            *failures = if refused { *failures + 1 } else { 0 };
+            *failures
+        };
+
+        if self
+            .watchdog_threshold
+            .is_some_and(|threshold| failures >= threshold)
+        {
+            warn!(
+                "Pi-hole refused {} consecutive connections, restarting FTL",
+                failures
+            );
+            match self.restart_ftl().await {
+                Ok(()) => {
+                    self.metrics.watchdog_restarts.inc();
+                    *self.connect_failures.lock().unwrap() = 0;
+                }
+                Err(e) => warn!("Watchdog failed to restart FTL: {}", e),
+            }
+        }
+    }
+
+    /// Ask Pi-hole to restart FTL
+    async fn restart_ftl(&self) -> Result<()> {
+        let mut request = self
+            .client
+            .post(self.api_url("action/restartdns"))
+            .header("accept", "application/json");
+
+        if let Some(sid) = self.sid() {
+            request = request.header("sid", sid);
+        }
+
+        request
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| PiholeError::from(e).context("restarting FTL"))?;
+        Ok(())
+    }
+
+    /// Fetch all Pi-hole data and update the metrics, for [`Self::update_metrics`]
+    async fn collect_metrics(&self) -> Result<()> {
+        self.metrics.start_update_cycle();
+        if let Some(started) = *self.session_started.lock().unwrap() {
+            set_if_changed(
+                &self.metrics.auth_session_age,
+                started.elapsed().as_secs() as i64,
+            );
+        }
+
         // Get summary stats
-        let summary_json = self.get_api_call("stats/summary").await?;
-        let summary: StatsResponse = serde_json::from_value(summary_json)?;
+        let summary: StatsResponse = self.get_api("stats/summary").await?;
 
         // Update 24h query type metrics
         for (query_type, count) in &summary.queries.types {
-            self.metrics
-                .query_by_type
-                .get_or_create(&QueryTypeLabels {
+            self.metrics.query_by_type.set(
+                &QueryTypeLabels {
                     query_type: query_type.clone(),
-                })
-                .set(*count as i64);
+                },
+                *count as i64,
+            );
         }
 
-        // Update 24h query status metrics
+        // Update 24h query status metrics, merging statuses that map to the same value
+        let mut summary_status_cnt: HashMap<&str, u64> = HashMap::new();
         for (status, count) in &summary.queries.status {
-            self.metrics
-                .query_by_status
-                .get_or_create(&QueryStatusLabels {
-                    query_status: status.clone(),
-                })
-                .set(*count as i64);
+            *summary_status_cnt
+                .entry(self.status_label(status))
+                .or_insert(0) += count;
+        }
+        for (status, count) in &summary_status_cnt {
+            self.metrics.query_by_status.set(
+                &QueryStatusLabels {
+                    query_status: status.to_string(),
+                },
+                *count as i64,
+            );
         }
 
         // Update 24h reply type metrics
         for (reply_type, count) in &summary.queries.replies {
-            self.metrics
-                .query_replies
-                .get_or_create(&ReplyTypeLabels {
+            self.metrics.query_replies.set(
+                &ReplyTypeLabels {
                     reply_type: reply_type.clone(),
-                })
-                .set(*count as i64);
+                },
+                *count as i64,
+            );
+        }
+
+        // Update 24h response code metrics, derived from the reply types
+        let mut summary_rcode_cnt: HashMap<&str, u64> = HashMap::new();
+        for (reply_type, count) in &summary.queries.replies {
+            if let Some(rcode) = reply_rcode(reply_type) {
+                *summary_rcode_cnt.entry(rcode).or_insert(0) += count;
+            }
+        }
+        for (rcode, count) in &summary_rcode_cnt {
+            self.metrics.query_rcode_24h.set(
+                &RcodeLabels {
+                    rcode: rcode.to_string(),
+                },
+                *count as i64,
+            );
         }
 
         // Update total counts
-        self.metrics
-            .query_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.query_count.set(
+            &CategoryLabels {
                 category: "total".to_string(),
-            })
-            .set(summary.queries.total as i64);
+            },
+            summary.queries.total as i64,
+        );
 
-        self.metrics
-            .query_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.query_count.set(
+            &CategoryLabels {
                 category: "blocked".to_string(),
-            })
-            .set(summary.queries.blocked as i64);
+            },
+            summary.queries.blocked as i64,
+        );
 
-        self.metrics
-            .query_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.query_count.set(
+            &CategoryLabels {
                 category: "unique".to_string(),
-            })
-            .set(summary.queries.unique_domains as i64);
+            },
+            summary.queries.unique_domains as i64,
+        );
 
-        self.metrics
-            .query_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.query_count.set(
+            &CategoryLabels {
                 category: "forwarded".to_string(),
-            })
-            .set(summary.queries.forwarded as i64);
+            },
+            summary.queries.forwarded as i64,
+        );
 
-        self.metrics
-            .query_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.query_count.set(
+            &CategoryLabels {
                 category: "cached".to_string(),
-            })
-            .set(summary.queries.cached as i64);
+            },
+            summary.queries.cached as i64,
+        );
+
+        // Estimate in-flight queries from how many more queries arrived than were
+        // answered since the last scrape; FTL does not expose this directly
+        let answered = summary.queries.replies.values().sum::<u64>()
+            - summary.queries.replies.get("UNKNOWN").copied().unwrap_or(0);
+        let previous = self
+            .previous_query_totals
+            .lock()
+            .unwrap()
+            .replace((summary.queries.total, answered));
+        if let Some((previous_total, previous_answered)) = previous {
+            let arrived = summary.queries.total as i64 - previous_total as i64;
+            let completed = answered as i64 - previous_answered as i64;
+            self.metrics.queries_in_flight.set(
+                &InFlightLabels {
+                    synthetic: "true".to_string(),
+                },
+                (arrived - completed).max(0),
+            );
+        }
 
         // Update client counts
-        self.metrics
-            .client_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.client_count.set(
+            &CategoryLabels {
                 category: "active".to_string(),
-            })
-            .set(summary.clients.active as i64);
+            },
+            summary.clients.active as i64,
+        );
 
-        self.metrics
-            .client_count
-            .get_or_create(&CategoryLabels {
+        self.metrics.client_count.set(
+            &CategoryLabels {
                 category: "total".to_string(),
-            })
-            .set(summary.clients.total as i64);
+            },
+            summary.clients.total as i64,
+        );
 
-        // Update domains being blocked
+        // Update NXDOMAIN share, FTL reports NXDOMAIN as a reply type
+        let nxdomain = summary
+            .queries
+            .replies
+            .get(NXDOMAIN_REPLY)
+            .copied()
+            .unwrap_or(0);
+        set_if_changed(&self.metrics.nxdomain_24h, nxdomain as i64);
         self.metrics
-            .domains_being_blocked
-            .set(summary.gravity.domains_being_blocked as i64);
+            .nxdomain_rate_24h
+            .set(ratio(nxdomain, summary.queries.total));
+        // Blocked queries are neither cached nor forwarded, so leave them out
+        self.metrics.cache_hit_rate_24h.set(ratio(
+            summary.queries.cached,
+            summary.queries.cached + summary.queries.forwarded,
+        ));
+
+        // Update domains being blocked
+        set_if_changed(
+            &self.metrics.domains_being_blocked,
+            summary.gravity.domains_being_blocked as i64,
+        );
+        set_if_changed(
+            &self.metrics.gravity_last_update,
+            summary.gravity.last_update as i64,
+        );
+        self.record_gravity_update(summary.gravity.last_update);
+        if summary.gravity.last_update > 0 {
+            set_if_changed(
+                &self.metrics.gravity_age,
+                (Utc::now().timestamp() - summary.gravity.last_update as i64).max(0),
+            );
+        }
+
+        if let Err(e) = self.update_group_metrics().await {
+            warn!("Failed to update group and blocklist metrics: {}", e);
+        }
+        if let Err(e) = self.update_audit_metrics().await {
+            warn!("Failed to update custom domain metrics: {}", e);
+        }
+        if let Err(e) = self.update_conditional_forwarding_metrics().await {
+            warn!("Failed to update conditional forwarding metrics: {}", e);
+        }
+        if let Err(e) = self.update_ftl_process_metrics().await {
+            warn!("Failed to update FTL process metrics: {}", e);
+        }
+        if let Err(e) = self.update_database_metrics().await {
+            warn!("Failed to update database metrics: {}", e);
+        }
 
         // Get upstream stats
-        let upstreams_json = self.get_api_call("stats/upstreams").await?;
-        let upstreams: UpstreamsResponse = serde_json::from_value(upstreams_json)?;
+        let upstreams: UpstreamsResponse = self.get_api("stats/upstreams").await?;
 
+        let mut ip_version_cnt: HashMap<&str, u64> = HashMap::new();
+        let mut forwarded_cnt: HashMap<String, u64> = HashMap::new();
         for upstream in &upstreams.upstreams {
+            // Port -1 marks FTL's pseudo upstreams (cache, blocklist), which forward nothing
+            if upstream.port >= 0 {
+                *forwarded_cnt
+                    .entry(format!("{}#{}", upstream.ip, upstream.port))
+                    .or_insert(0) += upstream.count;
+            }
+            if let Some(version) = ip_version(&upstream.ip) {
+                *ip_version_cnt.entry(version).or_insert(0) += upstream.count;
+            }
+            let name = if upstream.name.is_empty() {
+                self.upstream_name(&upstream.ip).await
+            } else {
+                upstream.name.clone()
+            };
+            let labels = UpstreamLabels {
+                ip: upstream.ip.clone(),
+                name,
+                port: upstream.port.to_string(),
+            };
             self.metrics
                 .query_upstream_count
-                .get_or_create(&UpstreamLabels {
-                    ip: upstream.ip.clone(),
-                    name: upstream.name.clone(),
-                    port: upstream.port.to_string(),
-                })
-                .set(upstream.count as i64);
+                .set(&labels, upstream.count as i64);
+            if self.max_upstream_age.is_some() {
+                self.upstream_last_seen
+                    .lock()
+                    .unwrap()
+                    .insert(labels, Instant::now());
+            }
+        }
+        self.expire_upstreams();
+        for version in ["v4", "v6"] {
+            self.metrics.upstream_ip_version_count.set(
+                &IpVersionLabels {
+                    version: version.to_string(),
+                },
+                ip_version_cnt.get(version).copied().unwrap_or(0) as i64,
+            );
         }
+        self.metrics
+            .upstream_query_entropy
+            .set(shannon_entropy(&forwarded_cnt));
+        set_if_changed(
+            &self.metrics.upstream_count_24h,
+            upstreams.upstreams.len() as i64,
+        );
+        set_if_changed(
+            &self.metrics.upstream_count_active,
+            upstreams
+                .upstreams
+                .iter()
+                .filter(|upstream| upstream.count > 0)
+                .count() as i64,
+        );
 
-        // Get 1-minute stats
+        // Get rolling window stats
         let now = Utc::now();
-        let last_min = now.timestamp() / 60 * 60;
-        let min_before = last_min - 60;
+        if !self.disable_window_metrics {
+            for window in &self.metrics.windows {
+                self.collect_window(window, now.timestamp()).await?;
+            }
+        }
+
+        if self.enable_historical_metrics {
+            self.update_historical_metrics(now.timestamp()).await?;
+        }
+
+        if self.enable_dhcp_metrics {
+            self.update_dhcp_metrics().await?;
+        }
+
+        if let Some(path) = &self.monitor_disk_path {
+            self.update_disk_metrics(path)?;
+        }
+
+        self.notify_changes();
 
-        let queries_json = self
-            .get_api_call(&format!(
-                "queries?from={}&until={}&length=1000000",
-                min_before, last_min
+        Ok(())
+    }
+
+    /// Update per-group blocked domain counts and per-blocklist freshness
+    ///
+    /// Failures are only logged by the caller, so a token that cannot read
+    /// groups or lists leaves these metrics untouched instead of failing the scrape.
+    async fn update_group_metrics(&self) -> Result<()> {
+        let groups: GroupsResponse = self.get_api("groups").await?;
+        let lists: ListsResponse = self.get_api("lists").await?;
+
+        let mut group_cnt: HashMap<u64, u64> = HashMap::new();
+        for list in lists
+            .lists
+            .iter()
+            .filter(|list| list.enabled && list.list_type == "block")
+        {
+            for group_id in &list.groups {
+                *group_cnt.entry(*group_id).or_insert(0) += list.number;
+            }
+        }
+
+        // Lists and groups can be removed or renamed, so drop series for those that have gone
+        self.metrics.blocklist_last_update.clear();
+        self.metrics.blocklist_invalid_domains.clear();
+        self.metrics.group_domains_blocked.clear();
+
+        // Update per-blocklist freshness metrics
+        for list in lists.lists.iter().filter(|list| list.list_type == "block") {
+            let labels = BlocklistLabels {
+                id: list.id.to_string(),
+                address: list.address.clone(),
+            };
+            self.metrics
+                .blocklist_last_update
+                .set(&labels, list.date_updated as i64);
+            self.metrics
+                .blocklist_invalid_domains
+                .set(&labels, list.invalid_domains as i64);
+        }
+
+        for group in &groups.groups {
+            self.metrics.group_domains_blocked.set(
+                &GroupLabels {
+                    group_id: group.id.to_string(),
+                    group_name: group.name.clone(),
+                },
+                group_cnt.get(&group.id).copied().unwrap_or(0) as i64,
+            );
+        }
+        Ok(())
+    }
+
+    /// Update CPU and memory usage of the FTL process
+    ///
+    /// FTL reports memory as a share of total RAM, so it is scaled by the host's RAM.
+    async fn update_ftl_process_metrics(&self) -> Result<()> {
+        let ftl: FtlInfoResponse = self.get_api("info/ftl").await?;
+        let system: SystemInfoResponse = self.get_api("info/system").await?;
+
+        self.metrics.ftl_cpu_usage_percent.set(ftl.ftl.cpu_percent);
+        let ram_bytes = system.system.memory.ram.total as f64 * 1024.0;
+        set_if_changed(
+            &self.metrics.ftl_memory_usage,
+            (ram_bytes * ftl.ftl.mem_percent / 100.0).round() as i64,
+        );
+        Ok(())
+    }
+
+    /// Update the size and time span of the long-term query database
+    async fn update_database_metrics(&self) -> Result<()> {
+        let database: DatabaseInfoResponse = self.get_api("info/database").await?;
+
+        set_if_changed(&self.metrics.database_queries, database.queries as i64);
+        set_if_changed(
+            &self.metrics.database_earliest_query,
+            database.earliest_timestamp as i64,
+        );
+        Ok(())
+    }
+
+    /// Update the conditional forwarding (reverse server) configuration
+    async fn update_conditional_forwarding_metrics(&self) -> Result<()> {
+        let config: DnsConfigResponse = self.get_api("config/dns/revServers").await?;
+        let dns = &config.config.dns;
+
+        set_if_changed(
+            &self.metrics.conditional_forwarding_enabled,
+            (dns.enabled_rev_servers() > 0) as i64,
+        );
+        set_if_changed(
+            &self.metrics.conditional_forwarding_targets,
+            dns.rev_servers.len() as i64,
+        );
+        Ok(())
+    }
+
+    /// Update DHCP server status and per-lease expiry
+    async fn update_dhcp_metrics(&self) -> Result<()> {
+        let config: DhcpConfigResponse = self.get_api("config/dhcp/active").await?;
+        let mut dhcp: DhcpStatusResponse = self.get_api("dhcp/leases").await?;
+        dhcp.enabled = config.config.dhcp.active;
+
+        set_if_changed(&self.metrics.dhcp_enabled, dhcp.enabled as i64);
+        set_if_changed(&self.metrics.dhcp_leases, dhcp.leases.len() as i64);
+
+        // Leases come and go, so drop series for leases that have gone
+        self.metrics.dhcp_lease_expiry.clear();
+        for lease in &dhcp.leases {
+            self.metrics.dhcp_lease_expiry.set(
+                &DhcpLeaseLabels {
+                    ip: lease.ip.clone(),
+                    hostname: lease.hostname.clone(),
+                },
+                lease.expiry as i64,
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Update custom allow/deny list sizes and count manual changes to them
+    ///
+    /// Pi-hole has no audit log endpoint, so changes are derived from each
+    /// domain's modification time. The first scrape only sets the baseline.
+    async fn update_audit_metrics(&self) -> Result<()> {
+        let domains: DomainsResponse = self.get_api("domains").await?;
+
+        let count = |domain_type: &str| {
+            domains
+                .domains
+                .iter()
+                .filter(|domain| domain.domain_type == domain_type)
+                .count() as i64
+        };
+        set_if_changed(&self.metrics.custom_allow_list_size, count("allow"));
+        set_if_changed(&self.metrics.custom_deny_list_size, count("deny"));
+
+        let latest = domains
+            .domains
+            .iter()
+            .map(|domain| domain.date_modified)
+            .max()
+            .unwrap_or(0);
+
+        let mut last_seen = self.last_audit_timestamp.lock().unwrap();
+        if let Some(previous) = *last_seen {
+            let changed = domains
+                .domains
+                .iter()
+                .filter(|domain| domain.date_modified > previous)
+                .count();
+            self.metrics.audit_events.inc_by(changed as u64);
+        }
+        let latest = latest.max(last_seen.unwrap_or(0));
+        *last_seen = Some(latest);
+        set_if_changed(&self.metrics.audit_last_event_timestamp, latest as i64);
+
+        Ok(())
+    }
+
+    /// Zero and stop tracking upstreams not reported within the maximum upstream age
+    fn expire_upstreams(&self) {
+        let Some(max_age) = self.max_upstream_age else {
+            return;
+        };
+
+        self.upstream_last_seen
+            .lock()
+            .unwrap()
+            .retain(|labels, last_seen| {
+                let fresh = last_seen.elapsed() < max_age;
+                if !fresh {
+                    debug!("Zeroing stale upstream {}#{}", labels.ip, labels.port);
+                    self.metrics.query_upstream_count.set(labels, 0);
+                }
+                fresh
+            });
+    }
+
+    /// Resolve the name for an upstream IP via reverse DNS, cached for the configured TTL
+    ///
+    /// Lookups that fail or find no PTR record cache an empty name.
+    async fn upstream_name(&self, ip: &str) -> String {
+        if let Some((name, resolved_at)) = self.upstream_names.lock().unwrap().get(ip)
+            && resolved_at.elapsed() < self.upstream_name_ttl
+        {
+            return name.clone();
+        }
+
+        let name = match ip.parse::<IpAddr>() {
+            Ok(addr) => match self.resolver.reverse_lookup(addr).await {
+                Ok(lookup) => lookup
+                    .answers()
+                    .iter()
+                    .find_map(|record| match &record.data {
+                        RData::PTR(ptr) => {
+                            Some(ptr.0.to_string().trim_end_matches('.').to_string())
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or_default(),
+                Err(e) => {
+                    debug!("Reverse lookup for {} failed: {}", ip, e);
+                    String::new()
+                }
+            },
+            Err(_) => String::new(),
+        };
+        debug!("Resolved upstream {} to {:?}", ip, name);
+
+        self.upstream_names
+            .lock()
+            .unwrap()
+            .insert(ip.to_string(), (name.clone(), Instant::now()));
+        name
+    }
+
+    /// Snapshot compared by /metrics/changes
+    ///
+    /// The exporter's own timing and scrape metrics change on every update, so they are ignored.
+    fn changes_snapshot(&self) -> Result<MetricsSnapshot> {
+        let mut snapshot = self.metrics_snapshot()?;
+        snapshot.retain(|series, _| {
+            !SELF_METRIC_PREFIXES
+                .iter()
+                .any(|prefix| series.starts_with(prefix))
+        });
+        Ok(snapshot)
+    }
+
+    /// Wake /metrics/changes subscribers if any metric value differs from the last update
+    ///
+    /// Nothing is encoded while there are no subscribers.
+    fn notify_changes(&self) {
+        if self.changes.receiver_count() == 0 {
+            return;
+        }
+        let snapshot = match self.changes_snapshot() {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                warn!("Failed to snapshot metrics for /metrics/changes: {}", e);
+                return;
+            }
+        };
+        self.changes.send_if_modified(|previous| {
+            if *previous == snapshot {
+                false
+            } else {
+                *previous = snapshot;
+                true
+            }
+        });
+    }
+
+    /// Subscribe to metric value changes, starting from the current values
+    pub fn subscribe_changes(&self) -> watch::Receiver<MetricsSnapshot> {
+        // The last snapshot is outdated if updates ran without subscribers
+        if self.changes.receiver_count() == 0 {
+            match self.changes_snapshot() {
+                Ok(snapshot) => {
+                    self.changes.send_replace(snapshot);
+                }
+                Err(e) => warn!("Failed to snapshot metrics for /metrics/changes: {}", e),
+            }
+        }
+        self.changes.subscribe()
+    }
+
+    /// Update the metrics for a single rolling window ending at `now`
+    async fn collect_window(&self, window: &WindowMetrics, now: i64) -> Result<()> {
+        // Windows end on the last whole minute
+        let until = now / 60 * 60;
+        let from = until - window.interval_secs as i64;
+
+        let queries_response: QueriesResponse = self
+            .get_api(&format!(
+                "queries?from={}&until={}&length={}",
+                from, until, QUERY_WINDOW_LENGTH
             ))
             .await?;
 
-        let queries_response: QueriesResponse = serde_json::from_value(queries_json)?;
+        let window_labels = WindowLabels {
+            window: window.suffix(),
+        };
+        let returned = queries_response.queries.len();
+        let truncated = returned >= QUERY_WINDOW_LENGTH;
+        if truncated {
+            warn!(
+                "Pi-hole returned {} queries for the {} window, the page limit; window metrics are undercounted",
+                returned, window_labels.window
+            );
+        }
+        self.metrics
+            .queries_in_window
+            .set(&window_labels, returned as i64);
+        self.metrics
+            .queries_truncated
+            .set(&window_labels, truncated as i64);
 
         let mut type_cnt: HashMap<String, u64> = HashMap::new();
         let mut status_cnt: HashMap<String, u64> = HashMap::new();
         let mut reply_cnt: HashMap<String, u64> = HashMap::new();
+        let mut rcode_cnt: HashMap<&str, u64> = HashMap::new();
         let mut client_cnt: HashMap<String, u64> = HashMap::new();
         let mut upstream_cnt: HashMap<String, u64> = HashMap::new();
+        let mut doh_cnt = 0;
+        let mut cached_cnt = 0;
+        // Forwarded and cached answers per upstream
+        let mut upstream_cache_cnt: HashMap<&str, (u64, u64)> = HashMap::new();
+        let mut forwarded_cnt = 0;
+        let mut rebinding_cnt = 0;
+        let mut special_type_cnt: HashMap<&str, u64> = HashMap::new();
+        let mut reason_cnt: HashMap<&str, u64> = HashMap::new();
+        let mut category_cnt: HashMap<&str, u64> = HashMap::new();
+        let mut client_type_cnt: HashMap<(String, &str), u64> = HashMap::new();
 
-        // Process queries for 1-minute metrics
+        // Process queries for window metrics, skipping IDs FTL returned twice
+        let mut seen_query_ids: HashSet<u64> = HashSet::new();
+        let mut duplicates = 0;
+        // Seeded by the window start so repeated scrapes sample the same queries
+        let mut rng = StdRng::seed_from_u64(from as u64);
+        let sampling = self.query_sample_rate < 1.0;
         for query in &queries_response.queries {
+            if let Some(id) = query.id
+                && !seen_query_ids.insert(id)
+            {
+                duplicates += 1;
+                continue;
+            }
+            if sampling && !rng.random_bool(self.query_sample_rate) {
+                continue;
+            }
+
             *type_cnt.entry(query.query_type.clone()).or_insert(0) += 1;
-            *status_cnt.entry(query.status.clone()).or_insert(0) += 1;
+            *status_cnt
+                .entry(self.status_label(&query.status).to_string())
+                .or_insert(0) += 1;
             *reply_cnt.entry(query.reply.reply_type.clone()).or_insert(0) += 1;
-            *client_cnt.entry(query.client.ip.clone()).or_insert(0) += 1;
+            if let Some(rcode) = query.rcode() {
+                *rcode_cnt.entry(rcode).or_insert(0) += 1;
+            }
+            let client = if self.strip_private_ips {
+                anonymize_ip(&query.client.ip)
+            } else {
+                query.client.ip.clone()
+            };
+            if self.per_client_type_metrics {
+                *client_type_cnt
+                    .entry((client.clone(), &query.query_type))
+                    .or_insert(0) += 1;
+            }
+            *client_cnt.entry(client).or_insert(0) += 1;
+            if query.is_doh() {
+                doh_cnt += 1;
+            }
+            // FTL only names an upstream for cached answers if it recorded one,
+            // the rest are grouped under "cache"
+            let upstream_or_cache = || query.upstream.as_deref().unwrap_or("cache");
+            if CACHED_STATUSES.contains(&query.status.as_str()) {
+                cached_cnt += 1;
+                upstream_cache_cnt
+                    .entry(upstream_or_cache())
+                    .or_insert((0, 0))
+                    .1 += 1;
+            } else if FORWARDED_STATUSES.contains(&query.status.as_str()) {
+                forwarded_cnt += 1;
+                upstream_cache_cnt
+                    .entry(upstream_or_cache())
+                    .or_insert((0, 0))
+                    .0 += 1;
+            }
+            if query.is_private_reply() {
+                rebinding_cnt += 1;
+            }
+            if query.status == SPECIAL_DOMAIN_STATUS {
+                *special_type_cnt.entry(&query.query_type).or_insert(0) += 1;
+            }
+            if let Some(reason) = blocking_reason(&query.status) {
+                *reason_cnt.entry(reason).or_insert(0) += 1;
+            }
+            if let Some(categorizer) = &self.categorizer {
+                *category_cnt
+                    .entry(categorizer.categorize(&query.domain))
+                    .or_insert(0) += 1;
+            }
 
             let upstream = match &query.upstream {
                 Some(upstream) => upstream.clone(),
@@ -235,71 +1553,954 @@ impl PiholeCollector {
             *upstream_cnt.entry(upstream).or_insert(0) += 1;
         }
 
-        // Update 1-minute metrics
+        if duplicates > 0 {
+            debug!(
+                "Skipped {} duplicate queries in {} window",
+                duplicates,
+                window.suffix()
+            );
+            self.metrics.duplicate_queries_skipped.inc_by(duplicates);
+        }
+
+        let top_clients = self
+            .per_client_type_metrics
+            .then(|| top_clients(&client_cnt, self.top_clients_limit));
+        let asn_cnt = self.asn.as_ref().map(|asn| {
+            let mut asn_cnt: HashMap<AsnInfo, u64> = HashMap::new();
+            for (client, count) in &client_cnt {
+                *asn_cnt.entry(asn.lookup(client)).or_insert(0) += count;
+            }
+            asn_cnt
+        });
+
+        // Hold the registry only while the window is cleared and repopulated, so a
+        // concurrent scrape never encodes it half-filled
+        let _registry = self.registry.lock().unwrap();
+        window.clear();
+
+        // Update window metrics, scaling sampled counts back to the full volume
+        let scale = |count: u64| (count as f64 / self.query_sample_rate).round() as i64;
         for (query_type, count) in &type_cnt {
-            self.metrics
-                .query_type_1m
-                .get_or_create(&QueryTypeLabels {
+            window.query_type.set(
+                &QueryTypeLabels {
                     query_type: query_type.clone(),
-                })
-                .set(*count as i64);
+                },
+                scale(*count),
+            );
         }
 
         for (status, count) in &status_cnt {
-            self.metrics
-                .query_status_1m
-                .get_or_create(&QueryStatusLabels {
+            window.query_status.set(
+                &QueryStatusLabels {
                     query_status: status.clone(),
-                })
-                .set(*count as i64);
+                },
+                scale(*count),
+            );
         }
 
         for (reply_type, count) in &reply_cnt {
-            self.metrics
-                .query_reply_1m
-                .get_or_create(&ReplyTypeLabels {
+            window.query_reply.set(
+                &ReplyTypeLabels {
                     reply_type: reply_type.clone(),
-                })
-                .set(*count as i64);
+                },
+                scale(*count),
+            );
+        }
+
+        for (rcode, count) in &rcode_cnt {
+            window.query_rcode.set(
+                &RcodeLabels {
+                    rcode: rcode.to_string(),
+                },
+                scale(*count),
+            );
         }
 
         for (client, count) in &client_cnt {
-            self.metrics
-                .query_client_1m
-                .get_or_create(&ClientLabels {
+            window.query_client.set(
+                &ClientLabels {
                     query_client: client.clone(),
-                })
-                .set(*count as i64);
+                },
+                scale(*count),
+            );
+        }
+
+        if let Some(top_clients) = &top_clients {
+            for ((client, query_type), count) in &client_type_cnt {
+                if !top_clients.contains(client.as_str()) {
+                    continue;
+                }
+                window.query_client_type.set(
+                    &ClientTypeLabels {
+                        client: client.to_string(),
+                        query_type: query_type.to_string(),
+                    },
+                    scale(*count),
+                );
+            }
+        }
+
+        if let Some(asn_cnt) = asn_cnt {
+            for (info, count) in asn_cnt {
+                window.query_client_asn.set(
+                    &AsnLabels {
+                        asn: info.asn,
+                        org: info.org,
+                    },
+                    scale(count),
+                );
+            }
+        }
+
+        for (upstream, (forwarded, cached)) in &upstream_cache_cnt {
+            for (served_from, count) in [("upstream", forwarded), ("cache", cached)] {
+                window
+                    .upstream_cache_ratio
+                    .get_or_create(&UpstreamCacheLabels {
+                        upstream: upstream.to_string(),
+                        served_from: served_from.to_string(),
+                    })
+                    .set(ratio(*count, forwarded + cached));
+            }
         }
 
         for (upstream, count) in &upstream_cnt {
-            self.metrics
-                .query_upstream_1m
-                .get_or_create(&UpstreamCountLabels {
+            window.query_upstream.set(
+                &UpstreamCountLabels {
                     query_upstream: upstream.clone(),
-                })
-                .set(*count as i64);
+                },
+                scale(*count),
+            );
+        }
+
+        set_if_changed(&window.doh_queries, scale(doh_cnt));
+        set_if_changed(&window.potential_dns_rebinding, scale(rebinding_cnt));
+        set_if_changed(
+            &window.special_domain_queries,
+            scale(special_type_cnt.values().sum()),
+        );
+        for (query_type, count) in &special_type_cnt {
+            window.special_domain_types.set(
+                &QueryTypeLabels {
+                    query_type: query_type.to_string(),
+                },
+                scale(*count),
+            );
+        }
+
+        let counted: u64 = type_cnt.values().sum();
+        let nxdomain = reply_cnt.get(NXDOMAIN_REPLY).copied().unwrap_or(0);
+        set_if_changed(&window.nxdomain, scale(nxdomain));
+        window.nxdomain_rate.set(ratio(nxdomain, counted));
+        window
+            .cache_hit_rate
+            .set(ratio(cached_cnt, cached_cnt + forwarded_cnt));
+
+        for (category, count) in &category_cnt {
+            window.query_category.set(
+                &CategoryLabels {
+                    category: category.to_string(),
+                },
+                scale(*count),
+            );
+        }
+
+        for (reason, count) in &reason_cnt {
+            window.blocked_reason.set(
+                &BlockReasonLabels {
+                    reason: reason.to_string(),
+                },
+                scale(*count),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Update disk usage metrics for the filesystem holding `path`
+    fn update_disk_metrics(&self, path: &Path) -> Result<()> {
+        let usage = disk::disk_usage(path)
+            .map_err(|e| e.context(format!("reading disk usage of {}", path.display())))?;
+        set_if_changed(&self.metrics.disk_usage, usage.used() as i64);
+        set_if_changed(&self.metrics.disk_free, usage.free as i64);
+        self.metrics.disk_usage_percent.set(usage.used_percent());
+        Ok(())
+    }
+
+    /// Update long-term query totals from the Pi-hole database
+    async fn update_historical_metrics(&self, now: i64) -> Result<()> {
+        let (_, longest) = HISTORICAL_PERIODS[HISTORICAL_PERIODS.len() - 1];
+        let history: HistoricalDataResponse = self
+            .get_api(&format!(
+                "history/database?from={}&until={}",
+                now - longest * 86400,
+                now
+            ))
+            .await?;
+
+        for (period, days) in HISTORICAL_PERIODS {
+            let from = (now - days * 86400) as u64;
+            let (queries, blocked) = history
+                .history
+                .iter()
+                .filter(|stats| stats.timestamp >= from)
+                .fold((0, 0), |(queries, blocked), stats| {
+                    (queries + stats.queries, blocked + stats.blocked)
+                });
+
+            let labels = HistoricalLabels {
+                period: period.to_string(),
+            };
+            self.metrics.historical_queries.set(&labels, queries as i64);
+            self.metrics.historical_blocked.set(&labels, blocked as i64);
         }
 
         Ok(())
     }
 
+    /// Check that every endpoint in [`API_SCHEMA`] still returns the fields we read
+    ///
+    /// Endpoints that cannot be queried count as check errors rather than invalid schemas.
+    pub async fn check_api_schema(&self) {
+        let mut valid = true;
+        for (endpoint, fields) in API_SCHEMA {
+            match self.get_api_call(endpoint).await {
+                Ok(json) => {
+                    let missing = missing_fields(&json, fields);
+                    if !missing.is_empty() {
+                        warn!(
+                            "Pi-hole API {} is missing fields: {}",
+                            endpoint,
+                            missing.join(", ")
+                        );
+                        valid = false;
+                    }
+                }
+                Err(e) => {
+                    warn!("Schema check could not query {}: {}", endpoint, e);
+                    self.metrics.api_schema_check_errors.inc();
+                }
+            }
+        }
+        set_if_changed(&self.metrics.api_schema_valid, valid as i64);
+    }
+
+    /// Update the TLS certificate expiry metric from the certificate served by Pi-hole
+    pub async fn update_tls_expiry(&self) -> Result<()> {
+        let expiry = tls::certificate_expiry(&self.host)
+            .await
+            .map_err(|e| e.context(format!("checking TLS certificate of {}", self.host)))?;
+        set_if_changed(&self.metrics.tls_cert_expiry, expiry);
+        Ok(())
+    }
+
+    /// Capture the current value of every exposed series
+    pub fn metrics_snapshot(&self) -> Result<MetricsSnapshot> {
+        let encoded = self.encode_metrics()?;
+        Ok(encoded
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.rsplit_once(' '))
+            .filter_map(|(series, value)| Some((series.to_string(), value.parse().ok()?)))
+            .collect())
+    }
+
     /// Encode metrics to Prometheus format
     pub fn encode_metrics(&self) -> Result<String> {
         let mut buffer = String::new();
-        let registry = self.registry.lock().unwrap();
-        encode(&mut buffer, &registry)?;
+        self.encode_metrics_to(&mut buffer)?;
         Ok(buffer)
     }
+
+    /// Encode metrics into `writer` as they are produced, applying filters and renames per line
+    pub fn encode_metrics_to<W: Write>(&self, writer: W) -> Result<()> {
+        let registries = self.registry.lock().unwrap();
+        self.process_lines(writer, |mut writer| {
+            for registry in registries.iter() {
+                encode_registry(&mut writer, registry)?;
+            }
+            encode_eof(&mut writer)
+        })
+    }
+
+    /// Encode metric family `index`, followed by `# EOF` for the last family
+    ///
+    /// Returns `None` past the last family. The registry is only locked for this family.
+    pub fn encode_family(&self, index: usize) -> Result<Option<String>> {
+        let registries = self.registry.lock().unwrap();
+        let Some(registry) = registries.get(index) else {
+            return Ok(None);
+        };
+        let mut buffer = String::new();
+        self.process_lines(&mut buffer, |mut writer| {
+            encode_registry(&mut writer, registry)?;
+            if index + 1 == registries.len() {
+                encode_eof(&mut writer)?;
+            }
+            Ok(())
+        })?;
+        Ok(Some(buffer))
+    }
+
+    /// Run `encode` against `writer`, applying filters and renames per line when configured
+    fn process_lines<W: Write>(
+        &self,
+        writer: W,
+        encode: impl FnOnce(&mut dyn Write) -> fmt::Result,
+    ) -> Result<()> {
+        if self.metrics_filter.is_none() && self.label_renames.is_empty() {
+            let mut writer = writer;
+            encode(&mut writer)?;
+        } else {
+            let mut writer = LineProcessor {
+                collector: self,
+                line: String::new(),
+                writer,
+            };
+            encode(&mut writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies [`filter_metrics`] and [`rename_labels`] to each complete line before passing it on
+struct LineProcessor<'a, W: Write> {
+    collector: &'a PiholeCollector,
+    line: String,
+    writer: W,
+}
+
+impl<W: Write> LineProcessor<'_, W> {
+    /// Process and forward everything buffered so far
+    fn flush(&mut self) -> fmt::Result {
+        if self.line.is_empty() {
+            return Ok(());
+        }
+        let mut line = std::mem::take(&mut self.line);
+        if let Some(filter) = &self.collector.metrics_filter {
+            line = filter_metrics(&line, filter);
+        }
+        if !self.collector.label_renames.is_empty() {
+            line = rename_labels(&line, &self.collector.label_renames);
+        }
+        self.writer.write_str(&line)
+    }
+}
+
+impl<W: Write> Write for LineProcessor<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut rest = s;
+        while let Some(newline) = rest.find('\n') {
+            self.line.push_str(&rest[..=newline]);
+            self.flush()?;
+            rest = &rest[newline + 1..];
+        }
+        self.line.push_str(rest);
+        Ok(())
+    }
+}
+
+/// Whether responses for `api_path` are cached with their ETag
+///
+/// Only fixed paths qualify: queries and searches differ on every call, and
+/// caching them would keep large bodies around for keys that never come back.
+fn is_etag_cacheable(api_path: &str) -> bool {
+    !api_path.contains('?')
+        && (ETAG_CACHED_PATHS.contains(&api_path)
+            || ETAG_CACHED_PREFIXES
+                .iter()
+                .any(|prefix| api_path.starts_with(prefix)))
+}
+
+/// Check that an API path prefix starts with `/` and has no trailing `/`
+fn validate_api_path_prefix(prefix: &str) -> Result<()> {
+    if !prefix.starts_with('/') || prefix.ends_with('/') {
+        return Err(PiholeError::Config(format!(
+            "--api-path-prefix must start with '/' and not end with '/', got {prefix:?}"
+        )));
+    }
+    Ok(())
+}
+
+/// Which of `fields` (JSON pointers) are absent from `json`
+fn missing_fields<'a>(json: &Value, fields: &[&'a str]) -> Vec<&'a str> {
+    fields
+        .iter()
+        .copied()
+        .filter(|field| json.pointer(field).is_none())
+        .collect()
+}
+
+/// Replace an RFC 1918 address with its /24 subnet, e.g. `192.168.1.0/24`
+///
+/// Other addresses, and values that are not addresses, are returned unchanged.
+fn anonymize_ip(ip: &str) -> String {
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(address)) if address.is_private() => {
+            let [a, b, c, _] = address.octets();
+            format!("{a}.{b}.{c}.0/24")
+        }
+        _ => ip.to_string(),
+    }
+}
+
+/// The `limit` clients with the most queries, or all of them when `limit` is 0
+fn top_clients(client_cnt: &HashMap<String, u64>, limit: usize) -> HashSet<&str> {
+    let mut clients: Vec<(&str, u64)> = client_cnt
+        .iter()
+        .map(|(client, count)| (client.as_str(), *count))
+        .collect();
+    if limit > 0 && clients.len() > limit {
+        // Ties broken by address so the selection is stable between scrapes
+        clients.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        clients.truncate(limit);
+    }
+    clients.into_iter().map(|(client, _)| client).collect()
+}
+
+/// Shannon entropy in bits of the distribution given by `counts`, 0 when it is empty
+fn shannon_entropy(counts: &HashMap<String, u64>) -> f64 {
+    let total: u64 = counts.values().sum();
+    counts
+        .values()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = ratio(*count, total);
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// `part / total`, or 0 when there is nothing to divide
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+/// Endpoint of an API path, without its query string
+fn api_endpoint(api_path: &str) -> &str {
+    api_path.split_once('?').map_or(api_path, |(path, _)| path)
+}
+
+/// IP version label for an upstream address, `None` for entries like `blocklist` or `cache`
+fn ip_version(ip: &str) -> Option<&'static str> {
+    match ip.parse::<IpAddr>().ok()? {
+        IpAddr::V4(_) => Some("v4"),
+        IpAddr::V6(_) => Some("v6"),
+    }
+}
+
+/// Parse a version like `v6.0.1` into (major, minor, patch), missing parts are 0
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version
+        .trim_start_matches('v')
+        .split(['.', '-'])
+        .map(str::parse::<u64>);
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().and_then(|part| part.ok()).unwrap_or(0);
+    let patch = parts.next().and_then(|part| part.ok()).unwrap_or(0);
+    Some((major, minor, patch))
+}
+
+/// Why FTL blocked a query, derived from its raw status, or `None` if it was not blocked
+fn blocking_reason(status: &str) -> Option<&'static str> {
+    if status.starts_with("CNAME") || status.ends_with("_CNAME") {
+        Some("cname")
+    } else if status.starts_with("GRAVITY") {
+        Some("gravity")
+    } else if status.starts_with("REGEX") {
+        Some("regex")
+    } else if status.starts_with("EXACT") || status == "DENYLIST" {
+        Some("exact")
+    } else {
+        None
+    }
+}
+
+/// Drop sample lines from encoded metrics whose `{...}` label set matches `filter`
+fn filter_metrics(encoded: &str, filter: &Regex) -> String {
+    encoded
+        .lines()
+        .filter(|line| {
+            line.starts_with('#')
+                || !line
+                    .find('{')
+                    .zip(line.rfind('}'))
+                    .is_some_and(|(start, end)| filter.is_match(&line[start..=end]))
+        })
+        .flat_map(|line| [line, "\n"])
+        .collect()
+}
+
+/// Rewrite label keys in encoded metrics according to `renames`
+///
+/// Runs after [`filter_metrics`], so filters match the original label names.
+fn rename_labels(encoded: &str, renames: &HashMap<String, String>) -> String {
+    let mut output = String::with_capacity(encoded.len());
+    for line in encoded.lines() {
+        match line.find('{') {
+            Some(start) if !line.starts_with('#') => {
+                output.push_str(&line[..=start]);
+                rename_label_set(&line[start + 1..], renames, &mut output);
+            }
+            _ => output.push_str(line),
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Copy `key="value",...}rest` into `output`, renaming keys along the way
+fn rename_label_set(mut rest: &str, renames: &HashMap<String, String>, output: &mut String) {
+    while !rest.starts_with('}') {
+        let Some(eq) = rest.find('=') else {
+            break;
+        };
+        let key = &rest[..eq];
+        output.push_str(renames.get(key).map_or(key, String::as_str));
+        output.push('=');
+
+        // Skip over the quoted value, which may contain escaped quotes
+        let value = &rest[eq + 1..];
+        let bytes = value.as_bytes();
+        let mut end = 1;
+        while end < bytes.len() {
+            match bytes[end] {
+                b'\\' => end += 2,
+                b'"' => {
+                    end += 1;
+                    break;
+                }
+                _ => end += 1,
+            }
+        }
+        let end = end.min(value.len());
+        output.push_str(&value[..end]);
+        rest = &value[end..];
+
+        if let Some(next) = rest.strip_prefix(',') {
+            output.push(',');
+            rest = next;
+        }
+    }
+    output.push_str(rest);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_status_mapper() {
+        let mapper = StatusMapper::new([
+            ("CACHE_STALE".to_string(), "CACHE".to_string()),
+            ("CUSTOM".to_string(), "FORWARDED".to_string()),
+        ]);
+
+        assert_eq!(mapper.map("CACHE_STALE"), "CACHE");
+        assert_eq!(mapper.map("CUSTOM"), "FORWARDED");
+        assert_eq!(mapper.map("GRAVITY"), "GRAVITY");
+    }
+
+    #[test]
+    fn test_status_filter() {
+        let filter = StatusFilter::new(["GRAVITY".to_string(), "FORWARDED".to_string()]);
+        assert_eq!(filter.filter_status("GRAVITY"), "GRAVITY");
+        assert_eq!(filter.filter_status("SPECIAL_DOMAIN"), StatusFilter::OTHER);
+
+        let filter = StatusFilter::default();
+        assert_eq!(filter.filter_status("SPECIAL_DOMAIN"), "SPECIAL_DOMAIN");
+    }
 
     #[tokio::test]
     async fn test_pihole_collector_creation() {
-        let collector = PiholeCollector::new("localhost".to_string(), false, None).await;
-        assert!(collector.is_ok());
+        let args = Args::parse_from(["pihole-exporter", "--pihole", "127.0.0.1:1"]);
+        let collector = PiholeCollector::new(&args).await;
+        let error = collector.unwrap_err();
+        assert!(matches!(error.root(), PiholeError::Http(e) if e.is_connect()));
+        assert!(error.to_string().starts_with("api call to stats/summary: "));
+    }
+
+    #[test]
+    fn test_notify_changes() {
+        let collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        collector.metrics.domains_being_blocked.set(7);
+        collector.notify_changes();
+        // Skipped without subscribers, the baseline is taken on subscribe
+        assert!(collector.changes.borrow().is_empty());
+        let changes = collector.subscribe_changes();
+        assert_eq!(
+            changes.borrow().get("pihole_domains_being_blocked"),
+            Some(&7.0)
+        );
+
+        collector.notify_changes();
+        assert!(!changes.has_changed().unwrap());
+
+        collector.metrics.domains_being_blocked.set(42);
+        collector.notify_changes();
+        assert!(changes.has_changed().unwrap());
+    }
+
+    #[test]
+    fn test_encode_metrics_to_applies_filter_per_line() {
+        let mut collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        collector.metrics_filter = Some(Regex::new(r#"window="1m""#).unwrap());
+        collector.label_renames = HashMap::from_iter([("window".to_string(), "w".to_string())]);
+        collector.metrics.queries_in_window.set(
+            &WindowLabels {
+                window: "1m".to_string(),
+            },
+            5,
+        );
+
+        let mut encoded = String::new();
+        collector.encode_metrics_to(&mut encoded).unwrap();
+        assert!(!encoded.contains("pihole_queries_in_window{"));
+        assert!(encoded.ends_with("# EOF\n"));
+        assert_eq!(encoded, collector.encode_metrics().unwrap());
+    }
+
+    #[test]
+    fn test_missing_fields() {
+        let json = serde_json::json!({"queries": {"total": 1}, "gravity": {}});
+        assert_eq!(
+            missing_fields(
+                &json,
+                &[
+                    "/queries/total",
+                    "/queries/blocked",
+                    "/gravity/domains_being_blocked"
+                ]
+            ),
+            ["/queries/blocked", "/gravity/domains_being_blocked"]
+        );
+    }
+
+    #[test]
+    fn test_validate_api_path_prefix() {
+        assert!(validate_api_path_prefix("/api").is_ok());
+        assert!(validate_api_path_prefix("/pihole/api").is_ok());
+        assert!(validate_api_path_prefix("api").is_err());
+        assert!(validate_api_path_prefix("/api/").is_err());
+        assert!(validate_api_path_prefix("/").is_err());
+    }
+
+    #[test]
+    fn test_record_gravity_update() {
+        let collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+
+        collector.record_gravity_update(1725194639);
+        collector.record_gravity_update(1725194639);
+        collector.record_gravity_update(0);
+        assert_eq!(collector.metrics.config_changed.get(), 0);
+
+        collector.record_gravity_update(1725281039);
+        assert_eq!(collector.metrics.config_changed.get(), 1);
+    }
+
+    #[test]
+    fn test_instance_name_label() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["pihole-exporter", "--instance-name", "home-pihole"]);
+        let collector =
+            PiholeCollector::with_base(&args, "http://127.0.0.1:1".to_string()).unwrap();
+        let encoded = collector.encode_metrics().unwrap();
+
+        assert!(encoded.contains("pihole_domains_being_blocked{instance=\"home-pihole\"} 0"));
+    }
+
+    #[test]
+    fn test_base_labels() {
+        use clap::Parser;
+
+        let args = Args::parse_from([
+            "pihole-exporter",
+            "--base-labels",
+            "environment=prod,datacenter=us-east",
+            "--instance-name",
+            "home-pihole",
+        ]);
+        let collector =
+            PiholeCollector::with_base(&args, "http://127.0.0.1:1".to_string()).unwrap();
+        let encoded = collector.encode_metrics().unwrap();
+
+        assert!(encoded.contains(
+            "pihole_domains_being_blocked{environment=\"prod\",datacenter=\"us-east\",instance=\"home-pihole\"} 0"
+        ));
+    }
+
+    #[test]
+    fn test_scrape_mode() {
+        use clap::Parser;
+
+        let args = Args::parse_from(["pihole-exporter"]);
+        assert_eq!(ScrapeMode::from(&args), ScrapeMode::OnDemand);
+        let args = Args::parse_from(["pihole-exporter", "--scrape-interval-secs", "15"]);
+        assert_eq!(
+            ScrapeMode::from(&args),
+            ScrapeMode::Background(Duration::from_secs(15))
+        );
+    }
+
+    #[test]
+    fn test_anonymize_ip() {
+        assert_eq!(anonymize_ip("192.168.1.42"), "192.168.1.0/24");
+        assert_eq!(anonymize_ip("10.20.30.40"), "10.20.30.0/24");
+        assert_eq!(anonymize_ip("8.8.8.8"), "8.8.8.8");
+        assert_eq!(anonymize_ip("fd00::1"), "fd00::1");
+        assert_eq!(anonymize_ip("UNKNOWN"), "UNKNOWN");
+    }
+
+    #[test]
+    fn test_top_clients() {
+        let client_cnt = HashMap::from_iter([
+            ("10.0.0.1".to_string(), 5),
+            ("10.0.0.2".to_string(), 9),
+            ("10.0.0.3".to_string(), 5),
+        ]);
+
+        let top = top_clients(&client_cnt, 2);
+        assert_eq!(top, HashSet::from_iter(["10.0.0.2", "10.0.0.1"]));
+        assert_eq!(top_clients(&client_cnt, 0).len(), 3);
+    }
+
+    #[test]
+    fn test_shannon_entropy() {
+        let counts = |values: &[u64]| -> HashMap<String, u64> {
+            values
+                .iter()
+                .enumerate()
+                .map(|(i, count)| (i.to_string(), *count))
+                .collect()
+        };
+
+        assert_eq!(shannon_entropy(&counts(&[])), 0.0);
+        assert_eq!(shannon_entropy(&counts(&[42, 0])), 0.0);
+        assert_eq!(shannon_entropy(&counts(&[5, 5, 5, 5])), 2.0);
+        let skewed = shannon_entropy(&counts(&[90, 10]));
+        assert!(skewed > 0.0 && skewed < 1.0);
+    }
+
+    #[test]
+    fn test_ratio() {
+        assert_eq!(ratio(25, 100), 0.25);
+        assert_eq!(ratio(3, 0), 0.0);
+    }
+
+    #[test]
+    fn test_ip_version() {
+        assert_eq!(ip_version("8.8.8.8"), Some("v4"));
+        assert_eq!(ip_version("2001:4860:4860::8888"), Some("v6"));
+        assert_eq!(ip_version("blocklist"), None);
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(parse_version("v6.0.1"), Some((6, 0, 1)));
+        assert_eq!(parse_version("v6.1"), Some((6, 1, 0)));
+        assert_eq!(parse_version("vDev-abc"), None);
+    }
+
+    #[test]
+    fn test_blocking_reason() {
+        assert_eq!(blocking_reason("GRAVITY"), Some("gravity"));
+        assert_eq!(blocking_reason("REGEX"), Some("regex"));
+        assert_eq!(blocking_reason("DENYLIST"), Some("exact"));
+        assert_eq!(blocking_reason("GRAVITY_CNAME"), Some("cname"));
+        assert_eq!(blocking_reason("FORWARDED"), None);
+    }
+
+    #[test]
+    fn test_api_timing_rolling_average() {
+        let mut timing = ApiTimingState::default();
+        assert_eq!(timing.record("stats/summary", 1.0), 1.0);
+        assert_eq!(timing.record("stats/summary", 3.0), 2.0);
+        assert_eq!(timing.record("queries", 5.0), 5.0);
+
+        for _ in 0..API_TIMING_SAMPLES {
+            timing.record("stats/summary", 0.5);
+        }
+        assert_eq!(timing.record("stats/summary", 0.5), 0.5);
+    }
+
+    #[test]
+    fn test_filter_metrics() {
+        let encoded = "# HELP pihole_upstream_count Upstream queries.\n\
+            # TYPE pihole_upstream_count gauge\n\
+            pihole_upstream_count{ip=\"10.0.0.1\",name=\"internal\",port=\"53\"} 5\n\
+            pihole_upstream_count{ip=\"1.1.1.1\",name=\"one.one.one.one\",port=\"53\"} 7\n\
+            pihole_domains_being_blocked 42\n\
+            # EOF\n";
+        let filter = Regex::new(r#"ip="10\."#).unwrap();
+
+        let filtered = filter_metrics(encoded, &filter);
+        assert!(!filtered.contains("internal"));
+        assert!(filtered.contains("one.one.one.one"));
+        assert!(filtered.contains("pihole_domains_being_blocked 42\n"));
+        assert!(filtered.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_rename_labels() {
+        let encoded = "# TYPE pihole_query_by_type gauge\n\
+            pihole_query_by_type{query_type=\"A\"} 3\n\
+            pihole_upstream_count{ip=\"1.1.1.1\",name=\"a,\\\"b}\",port=\"53\"} 7\n\
+            pihole_domains_being_blocked 42\n";
+        let renames = HashMap::from_iter([
+            ("query_type".to_string(), "type".to_string()),
+            ("port".to_string(), "upstream_port".to_string()),
+        ]);
+
+        let renamed = rename_labels(encoded, &renames);
+        assert_eq!(
+            renamed,
+            "# TYPE pihole_query_by_type gauge\n\
+            pihole_query_by_type{type=\"A\"} 3\n\
+            pihole_upstream_count{ip=\"1.1.1.1\",name=\"a,\\\"b}\",upstream_port=\"53\"} 7\n\
+            pihole_domains_being_blocked 42\n"
+        );
+    }
+
+    #[test]
+    fn test_record_stale_scrape() {
+        let collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        assert_eq!(collector.record_stale_scrape(), None);
+        assert_eq!(collector.metrics.stale_scrapes.get(), 0);
+
+        *collector.last_update.lock().unwrap() = Some(Instant::now() - Duration::from_secs(90));
+        let age = collector.record_stale_scrape().unwrap();
+        assert!(age >= Duration::from_secs(90));
+        assert_eq!(collector.metrics.stale_scrapes.get(), 1);
+        assert!(collector.metrics.stale_scrape_age.get() >= 90);
+    }
+
+    #[tokio::test]
+    async fn test_record_scrape_if_stale() {
+        let collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        *collector.last_update.lock().unwrap() = Some(Instant::now() - Duration::from_secs(90));
+        assert_eq!(collector.record_scrape_if_stale(), None);
+
+        assert!(collector.update_metrics().await.is_err());
+        assert!(collector.record_scrape_if_stale().unwrap() >= Duration::from_secs(90));
+        assert!(collector.record_scrape_if_stale().is_some());
+        assert_eq!(collector.metrics.stale_scrapes.get(), 2);
+        assert!(collector.metrics.stale_scrape_age.get() >= 90);
+    }
+
+    #[tokio::test]
+    async fn test_watchdog_counts_connect_failures() {
+        let mut collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        collector.watchdog_threshold = Some(3);
+
+        assert!(collector.update_metrics().await.is_err());
+        assert!(collector.update_metrics().await.is_err());
+        assert_eq!(*collector.connect_failures.lock().unwrap(), 2);
+        assert_eq!(collector.metrics.watchdog_restarts.get(), 0);
+    }
+
+    #[test]
+    fn test_expire_upstreams() {
+        let mut collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        collector.max_upstream_age = Some(Duration::from_secs(60));
+        let labels = UpstreamLabels {
+            ip: "192.0.2.1".to_string(),
+            name: "dns.example".to_string(),
+            port: "53".to_string(),
+        };
+        collector.metrics.query_upstream_count.set(&labels, 10);
+        collector
+            .upstream_last_seen
+            .lock()
+            .unwrap()
+            .insert(labels.clone(), Instant::now() - Duration::from_secs(120));
+
+        collector.expire_upstreams();
+
+        assert!(collector.upstream_last_seen.lock().unwrap().is_empty());
+        assert_eq!(
+            collector
+                .metrics
+                .query_upstream_count
+                .get_or_create(&labels)
+                .get(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_name_cache() {
+        let collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        collector.upstream_names.lock().unwrap().insert(
+            "192.0.2.1".to_string(),
+            ("dns.example".to_string(), Instant::now()),
+        );
+
+        assert_eq!(collector.upstream_name("192.0.2.1").await, "dns.example");
+        assert_eq!(collector.upstream_name("not-an-ip").await, "");
+    }
+
+    #[tokio::test]
+    async fn test_etag_cache_skips_window_queries() {
+        let app = axum::Router::new().fallback(|| async { ([(ETAG, "\"v1\"")], "{}") });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let collector = PiholeCollector::from_mock_server(format!("http://{addr}"));
+
+        collector
+            .get_api_call("queries?from=1717484400&until=1717484460&length=1000000")
+            .await
+            .unwrap();
+        assert!(collector.etag_cache.lock().unwrap().is_empty());
+
+        collector.get_api_call("stats/summary").await.unwrap();
+        assert!(
+            collector
+                .etag_cache
+                .lock()
+                .unwrap()
+                .contains_key("stats/summary")
+        );
+
+        server.abort();
+    }
+
+    #[test]
+    fn test_scrape_jitter_bounds() {
+        let period = Duration::from_secs(15);
+        assert_eq!(initial_scrape_delay(Duration::ZERO), Duration::ZERO);
+        assert_eq!(next_scrape_delay(period, Duration::ZERO), period);
+        for _ in 0..1000 {
+            assert!(initial_scrape_delay(Duration::from_secs(10)) < Duration::from_secs(10));
+            let delay = next_scrape_delay(period, Duration::from_secs(10));
+            assert!((Duration::from_secs(10)..=Duration::from_secs(20)).contains(&delay));
+        }
+        // Jitter wider than twice the period never goes below zero
+        for _ in 0..1000 {
+            assert!(
+                next_scrape_delay(Duration::from_secs(1), Duration::from_secs(10))
+                    <= Duration::from_secs(6)
+            );
+        }
+    }
+
+    #[test]
+    fn test_metrics_snapshot() {
+        let collector = PiholeCollector::from_mock_server("http://127.0.0.1:1".to_string());
+        collector.metrics.domains_being_blocked.set(42);
+
+        let snapshot = collector.metrics_snapshot().unwrap();
+        assert_eq!(snapshot.get("pihole_domains_being_blocked"), Some(&42.0));
+        assert_eq!(
+            snapshot.get("pihole_cardinality_limit_hits_total"),
+            Some(&0.0)
+        );
     }
 }
@@ -0,0 +1,92 @@
+//! Named Pi-hole connection profiles loaded from a YAML file
+
+use std::{fs, path::Path};
+
+use secrecy::SecretString;
+use serde::Deserialize;
+
+use crate::{Args, PiholeError, Result};
+
+/// Connection settings for one Pi-hole
+#[derive(Debug, Deserialize)]
+pub struct InstanceProfile {
+    pub name: String,
+    /// Pi-hole host, as for `--pihole`
+    pub host: String,
+    #[serde(default)]
+    pub tls: bool,
+    pub password: Option<SecretString>,
+}
+
+/// Contents of a `--profiles-file`
+#[derive(Debug, Deserialize)]
+pub struct ProfileConfig {
+    pub profiles: Vec<InstanceProfile>,
+}
+
+impl ProfileConfig {
+    /// Load profiles from a YAML file with a `profiles` list
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(serde_yaml::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    /// Point `args` at the profile called `name`
+    ///
+    /// The profile's host and TLS setting replace the command line ones. Its
+    /// password does too when it has one, otherwise `--password` is kept.
+    pub fn apply(&self, name: &str, args: &mut Args) -> Result<()> {
+        let profile = self
+            .profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .ok_or_else(|| PiholeError::Config(format!("no profile named `{name}`")))?;
+
+        args.pihole = profile.host.clone();
+        args.tls = profile.tls;
+        if let Some(password) = &profile.password {
+            args.password = Some(password.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use secrecy::ExposeSecret;
+
+    use super::*;
+
+    const PROFILES: &str = "
+profiles:
+  - name: home
+    host: pihole.home:8080
+    password: home-secret
+  - name: office
+    host: pihole.office
+    tls: true
+";
+
+    #[test]
+    fn test_apply_profile() {
+        let config: ProfileConfig = serde_yaml::from_str(PROFILES).unwrap();
+
+        let mut args = Args::parse_from(["pihole-exporter", "--password", "cli-secret"]);
+        config.apply("home", &mut args).unwrap();
+        assert_eq!(args.pihole, "pihole.home:8080");
+        assert!(!args.tls);
+        assert_eq!(args.password.unwrap().expose_secret(), "home-secret");
+
+        let mut args = Args::parse_from(["pihole-exporter", "--password", "cli-secret"]);
+        config.apply("office", &mut args).unwrap();
+        assert_eq!(args.pihole, "pihole.office");
+        assert!(args.tls);
+        assert_eq!(args.password.unwrap().expose_secret(), "cli-secret");
+
+        let mut args = Args::parse_from(["pihole-exporter"]);
+        assert!(matches!(
+            config.apply("lab", &mut args),
+            Err(PiholeError::Config(_))
+        ));
+    }
+}
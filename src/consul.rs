@@ -0,0 +1,84 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde_json::json;
+use tracing::info;
+
+use crate::Result;
+
+/// How often Consul polls the exporter health check
+const HEALTH_CHECK_INTERVAL: &str = "15s";
+
+/// A service registered with the local Consul agent
+#[derive(Debug)]
+pub struct ConsulRegistration {
+    client: Client,
+    base: String,
+    service_id: String,
+}
+
+impl ConsulRegistration {
+    /// Register the exporter listening on `host:port` with the Consul agent at `consul_url`
+    pub async fn register(
+        consul_url: &str,
+        service_name: &str,
+        tags: &[String],
+        host: &str,
+        port: u16,
+    ) -> Result<Self> {
+        let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+        let base = consul_url.trim_end_matches('/').to_string();
+        let service_id = format!("{service_name}-{host}-{port}");
+
+        // Let Consul fill in the agent address when bound to all interfaces
+        let address = match host {
+            "0.0.0.0" | "::" => "",
+            host => host,
+        };
+        let check_host = if address.is_empty() {
+            "127.0.0.1"
+        } else {
+            address
+        };
+
+        let registration = json!({
+            "ID": service_id,
+            "Name": service_name,
+            "Tags": tags,
+            "Address": address,
+            "Port": port,
+            "Check": {
+                "HTTP": format!("http://{check_host}:{port}/healthz"),
+                "Interval": HEALTH_CHECK_INTERVAL,
+            },
+        });
+
+        client
+            .put(format!("{base}/v1/agent/service/register"))
+            .json(&registration)
+            .send()
+            .await?
+            .error_for_status()?;
+        info!("Registered Consul service {}", service_id);
+
+        Ok(Self {
+            client,
+            base,
+            service_id,
+        })
+    }
+
+    /// Remove the service from the Consul agent
+    pub async fn deregister(&self) -> Result<()> {
+        self.client
+            .put(format!(
+                "{}/v1/agent/service/deregister/{}",
+                self.base, self.service_id
+            ))
+            .send()
+            .await?
+            .error_for_status()?;
+        info!("Deregistered Consul service {}", self.service_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::{fs, path::Path};
+
+use sysinfo::Disks;
+
+use crate::{PiholeError, Result};
+
+/// Space on the filesystem holding a path, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskUsage {
+    pub total: u64,
+    pub free: u64,
+}
+
+impl DiskUsage {
+    /// Bytes in use
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
+
+    /// Share of the filesystem in use, 0-100
+    pub fn used_percent(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.used() as f64 / self.total as f64 * 100.0
+    }
+}
+
+/// Usage of the filesystem that `path` lives on
+///
+/// Picks the disk with the longest mount point containing `path`.
+pub fn disk_usage(path: &Path) -> Result<DiskUsage> {
+    let path = fs::canonicalize(path)?;
+    let disks = Disks::new_with_refreshed_list();
+    disks
+        .list()
+        .iter()
+        .filter(|disk| path.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())
+        .map(|disk| DiskUsage {
+            total: disk.total_space(),
+            free: disk.available_space(),
+        })
+        .ok_or_else(|| {
+            PiholeError::Io(std::io::Error::other(format!(
+                "no mounted filesystem contains {}",
+                path.display()
+            )))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_used_percent() {
+        let usage = DiskUsage {
+            total: 200,
+            free: 50,
+        };
+        assert_eq!(usage.used(), 150);
+        assert_eq!(usage.used_percent(), 75.0);
+        assert_eq!(DiskUsage { total: 0, free: 0 }.used_percent(), 0.0);
+    }
+}
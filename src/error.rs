@@ -0,0 +1,198 @@
+use reqwest::StatusCode;
+use std::{error::Error, fmt, io, time::Duration};
+
+/// Errors returned by the exporter
+#[derive(Debug)]
+pub enum PiholeError {
+    /// The request to Pi-hole (or another HTTP service) failed
+    Http(reqwest::Error),
+    /// A response body was not the JSON we expected
+    Json(serde_json::Error),
+    /// A response parsed as JSON but not into the shape we expected
+    InvalidResponse(String),
+    /// Pi-hole rejected the login
+    Auth(String),
+    /// Pi-hole is rate-limiting logins, retry after the given duration
+    AuthRateLimited(Duration),
+    /// Metrics could not be encoded
+    Encode(fmt::Error),
+    /// A local I/O operation failed
+    Io(io::Error),
+    /// The TLS certificate could not be fetched or parsed
+    Tls(String),
+    /// A configuration value is invalid
+    Config(String),
+    /// Tracing or span export could not be set up
+    Telemetry(String),
+    /// Another error, with a description of what was being done when it happened
+    Context {
+        context: String,
+        source: Box<PiholeError>,
+    },
+}
+
+impl PiholeError {
+    /// Wrap this error with a description of what was being done, e.g. `api call to stats/summary`
+    pub fn context(self, context: impl Into<String>) -> Self {
+        Self::Context {
+            context: context.into(),
+            source: Box::new(self),
+        }
+    }
+
+    /// Broad cause of the error for `pihole_last_error_type`
+    ///
+    /// 1=network, 2=auth, 3=parse, 4=timeout; 0 is reserved for no error.
+    /// Errors that are none of these count as parse errors, since the
+    /// exporter could reach Pi-hole but not turn its answer into metrics.
+    pub fn error_type(&self) -> i64 {
+        match self.root() {
+            Self::Http(e) if e.is_timeout() => 4,
+            Self::Http(e)
+                if e.status().is_some_and(|status| {
+                    status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN
+                }) =>
+            {
+                2
+            }
+            Self::Http(e) if e.is_decode() => 3,
+            Self::Http(_) | Self::Io(_) | Self::Tls(_) => 1,
+            Self::Auth(_) | Self::AuthRateLimited(_) => 2,
+            _ => 3,
+        }
+    }
+
+    /// The underlying error, without any context added by [`Self::context`]
+    pub fn root(&self) -> &Self {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+}
+
+impl fmt::Display for PiholeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http(e) => write!(f, "HTTP request failed: {e}"),
+            Self::Json(e) => write!(f, "invalid JSON response: {e}"),
+            Self::InvalidResponse(message) => write!(f, "unexpected API response: {message}"),
+            Self::Auth(message) => write!(f, "authentication failed: {message}"),
+            Self::AuthRateLimited(retry_after) => write!(
+                f,
+                "authentication rate-limited, retry after {}s",
+                retry_after.as_secs()
+            ),
+            Self::Encode(e) => write!(f, "failed to encode metrics: {e}"),
+            Self::Io(e) => write!(f, "I/O error: {e}"),
+            Self::Tls(message) => write!(f, "TLS error: {message}"),
+            Self::Config(message) => write!(f, "invalid configuration: {message}"),
+            Self::Telemetry(message) => write!(f, "telemetry error: {message}"),
+            Self::Context { context, source } => write!(f, "{context}: {source}"),
+        }
+    }
+}
+
+impl Error for PiholeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            Self::Json(e) => Some(e),
+            Self::Encode(e) => Some(e),
+            Self::Io(e) => Some(e),
+            Self::Context { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for PiholeError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl From<serde_json::Error> for PiholeError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<fmt::Error> for PiholeError {
+    fn from(e: fmt::Error) -> Self {
+        Self::Encode(e)
+    }
+}
+
+impl From<io::Error> for PiholeError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<native_tls::Error> for PiholeError {
+    fn from(e: native_tls::Error) -> Self {
+        Self::Tls(e.to_string())
+    }
+}
+
+impl From<regex::Error> for PiholeError {
+    fn from(e: regex::Error) -> Self {
+        Self::Config(e.to_string())
+    }
+}
+
+impl From<serde_yaml::Error> for PiholeError {
+    fn from(e: serde_yaml::Error) -> Self {
+        Self::Config(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for PiholeError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::Config(e.to_string())
+    }
+}
+
+impl From<maxminddb::MaxMindDbError> for PiholeError {
+    fn from(e: maxminddb::MaxMindDbError) -> Self {
+        Self::Config(format!("ASN database: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context_display_and_root() {
+        let error = PiholeError::InvalidResponse("missing queries".to_string())
+            .context("parsing stats/summary response")
+            .context("collecting metrics");
+
+        assert_eq!(
+            error.to_string(),
+            "collecting metrics: parsing stats/summary response: unexpected API response: missing queries"
+        );
+        assert!(matches!(error.root(), PiholeError::InvalidResponse(_)));
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn test_error_type() {
+        let io = io::Error::new(io::ErrorKind::ConnectionRefused, "refused");
+        assert_eq!(PiholeError::from(io).context("api call").error_type(), 1);
+        assert_eq!(
+            PiholeError::Auth("password incorrect".to_string()).error_type(),
+            2
+        );
+        assert_eq!(
+            PiholeError::AuthRateLimited(Duration::from_secs(5)).error_type(),
+            2
+        );
+        assert_eq!(
+            PiholeError::InvalidResponse("missing queries".to_string()).error_type(),
+            3
+        );
+    }
+}
@@ -1,47 +1,478 @@
-use crate::collector::PiholeCollector;
+use crate::{
+    api::{QueriesResponse, SearchResponse},
+    args::{Args, ArgsPublic},
+    collector::{PiholeCollector, ScrapeMode},
+};
+#[cfg(feature = "pprof")]
+use axum::http::header::CONTENT_TYPE;
 use axum::{
-    extract::State,
-    http::StatusCode,
+    Json,
+    body::Body,
+    extract::{Query, State},
+    http::{StatusCode, header::RETRY_AFTER},
     response::{IntoResponse, Response},
 };
-use std::sync::Arc;
+use serde::Deserialize;
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{SemaphorePermit, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::warn;
 
+/// Encoded metric families buffered by /metrics/stream ahead of the client
+const STREAM_CHANNEL_CAPACITY: usize = 8;
+
+/// How long a scrape waits for a free slot before it is answered with 429
+const SCRAPE_PERMIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Number of queries returned by /query when no length is given
+const DEFAULT_DOMAIN_QUERY_LENGTH: u32 = 100;
+
+/// Most queries /query asks Pi-hole for, whatever `length` is requested
+const MAX_DOMAIN_QUERY_LENGTH: u32 = 500;
+
+/// Length of a /debug/pprof/cpu profile when no duration is given, in seconds
+#[cfg(feature = "pprof")]
+const DEFAULT_CPU_PROFILE_SECS: u64 = 30;
+
+/// Longest /debug/pprof/cpu profile accepted, in seconds
+#[cfg(feature = "pprof")]
+const MAX_CPU_PROFILE_SECS: u64 = 300;
+
+/// Samples per second taken by /debug/pprof/cpu
+#[cfg(feature = "pprof")]
+const CPU_PROFILE_FREQUENCY: i32 = 99;
+
+/// Response returned by the /healthz endpoint
+#[derive(Debug, Clone)]
+pub struct HealthResponse {
+    pub status: StatusCode,
+    pub body: String,
+}
+
+impl From<&Args> for HealthResponse {
+    fn from(args: &Args) -> Self {
+        let body = if args.health_include_version {
+            format!(
+                "{} {}",
+                args.health_response_body,
+                env!("CARGO_PKG_VERSION")
+            )
+        } else {
+            args.health_response_body.clone()
+        };
+        Self {
+            // Clap limits the code to 200..300, which is always a valid status
+            status: StatusCode::from_u16(args.health_response_code).unwrap_or(StatusCode::OK),
+            body,
+        }
+    }
+}
+
+/// Query parameters for the /query endpoint
+#[derive(Debug, Deserialize)]
+pub struct QueryByDomainParams {
+    pub domain: String,
+    pub length: Option<u32>,
+}
+
+/// Query parameters for the /check endpoint
+#[derive(Debug, Deserialize)]
+pub struct CheckDomainParams {
+    pub domain: String,
+}
+
+/// Query parameters for the /debug/pprof/cpu endpoint
+#[cfg(feature = "pprof")]
+#[derive(Debug, Deserialize)]
+pub struct CpuProfileParams {
+    pub seconds: Option<u64>,
+}
+
+/// Whether `domain` is safe to pass to Pi-hole in a request path or query string
+fn is_valid_domain(domain: &str) -> bool {
+    !domain.is_empty()
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
 /// Handler for the /metrics endpoint
 ///
-/// Updates Pi-hole metrics and returns them in Prometheus format
+/// Updates Pi-hole metrics and returns them in Prometheus format.
+/// When the update fails after an earlier success, the previous values are served
+/// and counted in `pihole_stale_scrapes_total`. In [`ScrapeMode::Background`] the
+/// latest background values are returned without calling Pi-hole.
 pub async fn metrics_handler(State(collector): State<Arc<PiholeCollector>>) -> Response {
-    match collector.update_metrics().await {
-        Ok(()) => match collector.encode_metrics() {
-            Ok(metrics) => (
-                StatusCode::OK,
-                [("content-type", "text/plain; version=0.0.4; charset=utf-8")],
-                metrics,
-            )
-                .into_response(),
-            Err(e) => {
-                warn!("Failed to encode metrics: {}", e);
-                (
+    let start = Instant::now();
+    collector.exporter_metrics.scrapes.inc();
+
+    let response = match collector.scrape_mode {
+        ScrapeMode::OnDemand => update_and_encode(&collector).await,
+        ScrapeMode::Background(_) => background_metrics_response(&collector),
+    };
+
+    collector
+        .exporter_metrics
+        .scrape_duration
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
+
+/// Update the metrics for an on-demand scrape and encode them, falling back to stale values
+async fn update_and_encode(collector: &PiholeCollector) -> Response {
+    let permit = match acquire_scrape_permit(collector).await {
+        Ok(permit) => permit,
+        Err(response) => return response,
+    };
+    let response = match collector.update_metrics().await {
+        Ok(()) => encoded_metrics_response(collector),
+        Err(e) => {
+            warn!("Failed to collect metrics: {}", e);
+            collector.exporter_metrics.scrape_errors.inc();
+            match collector.record_stale_scrape() {
+                Some(age) => {
+                    warn!("Serving metrics from {}s ago", age.as_secs());
+                    encoded_metrics_response(collector)
+                }
+                None => (
                     StatusCode::INTERNAL_SERVER_ERROR,
-                    "Failed to encode metrics",
+                    "Failed to collect metrics",
                 )
-                    .into_response()
+                    .into_response(),
             }
-        },
+        }
+    };
+    drop(permit);
+    response
+}
+
+/// Encode the values from the latest background update, if there has been one
+///
+/// Serving them after that update failed counts as a stale scrape.
+fn background_metrics_response(collector: &PiholeCollector) -> Response {
+    if collector.last_update_age().is_none() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "Metrics not collected yet").into_response();
+    }
+    if let Some(age) = collector.record_scrape_if_stale() {
+        warn!("Serving metrics from {}s ago", age.as_secs());
+    }
+    encoded_metrics_response(collector)
+}
+
+/// Handler for the /metrics/changes endpoint
+///
+/// Waits until a metric value changes, then returns the metrics in Prometheus format.
+/// Responds with 204 No Content if nothing changes within the long-poll timeout.
+pub async fn metrics_changes_handler(State(collector): State<Arc<PiholeCollector>>) -> Response {
+    let mut changes = collector.subscribe_changes();
+    match tokio::time::timeout(collector.long_poll_timeout, changes.changed()).await {
+        Ok(Ok(())) => encoded_metrics_response(&collector),
+        Ok(Err(_)) | Err(_) => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+/// Handler for the /metrics/stream endpoint
+///
+/// Like /metrics, but sends the metrics one family at a time through a channel of
+/// [`STREAM_CHANNEL_CAPACITY`] families, so the full output is never held in memory
+/// and a slow reader only holds the registry while a single family is encoded.
+pub async fn metrics_streaming_handler(State(collector): State<Arc<PiholeCollector>>) -> Response {
+    let start = Instant::now();
+    collector.exporter_metrics.scrapes.inc();
+
+    if let Err(response) = prepare_stream(&collector).await {
+        collector
+            .exporter_metrics
+            .scrape_duration
+            .observe(start.elapsed().as_secs_f64());
+        return response;
+    }
+
+    let (sender, receiver) = mpsc::channel(STREAM_CHANNEL_CAPACITY);
+    tokio::spawn(stream_families(collector, sender, start));
+
+    (
+        StatusCode::OK,
+        [("content-type", "text/plain; version=0.0.4; charset=utf-8")],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    )
+        .into_response()
+}
+
+/// Bring the metrics up to date for /metrics/stream, as [`metrics_handler`] does
+async fn prepare_stream(collector: &PiholeCollector) -> std::result::Result<(), Response> {
+    match collector.scrape_mode {
+        ScrapeMode::OnDemand => {
+            let permit = acquire_scrape_permit(collector).await?;
+            let result = collector.update_metrics().await;
+            drop(permit);
+            if let Err(e) = result {
+                warn!("Failed to collect metrics: {}", e);
+                collector.exporter_metrics.scrape_errors.inc();
+                match collector.record_stale_scrape() {
+                    Some(age) => warn!("Streaming metrics from {}s ago", age.as_secs()),
+                    None => {
+                        return Err((
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Failed to collect metrics",
+                        )
+                            .into_response());
+                    }
+                }
+            }
+        }
+        ScrapeMode::Background(_) if collector.last_update_age().is_none() => {
+            return Err(
+                (StatusCode::SERVICE_UNAVAILABLE, "Metrics not collected yet").into_response(),
+            );
+        }
+        ScrapeMode::Background(_) => {
+            if let Some(age) = collector.record_scrape_if_stale() {
+                warn!("Streaming metrics from {}s ago", age.as_secs());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encode each metric family into `sender` until the last one or the client goes away
+///
+/// The scrape duration started at `start` is observed once the stream ends.
+async fn stream_families(
+    collector: Arc<PiholeCollector>,
+    sender: mpsc::Sender<io::Result<String>>,
+    start: Instant,
+) {
+    for index in 0.. {
+        let family = match collector.encode_family(index) {
+            Ok(Some(family)) => Ok(family),
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Failed to encode metrics: {}", e);
+                Err(io::Error::other(e.to_string()))
+            }
+        };
+        let failed = family.is_err();
+        if sender.send(family).await.is_err() || failed {
+            break;
+        }
+    }
+    collector
+        .exporter_metrics
+        .scrape_duration
+        .observe(start.elapsed().as_secs_f64());
+}
+
+/// Wait for one of the `--max-concurrent-scrapes` slots
+///
+/// Answers 429 with `Retry-After` when none frees up within [`SCRAPE_PERMIT_TIMEOUT`].
+async fn acquire_scrape_permit(
+    collector: &PiholeCollector,
+) -> std::result::Result<SemaphorePermit<'_>, Response> {
+    match tokio::time::timeout(SCRAPE_PERMIT_TIMEOUT, collector.scrape_semaphore.acquire()).await {
+        Ok(Ok(permit)) => Ok(permit),
+        Ok(Err(_)) | Err(_) => {
+            warn!("Too many concurrent scrapes, rejecting request");
+            Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, SCRAPE_PERMIT_TIMEOUT.as_secs().to_string())],
+                "Too many concurrent scrapes",
+            )
+                .into_response())
+        }
+    }
+}
+
+/// Encode the current metrics as a Prometheus text response
+fn encoded_metrics_response(collector: &PiholeCollector) -> Response {
+    match collector.encode_metrics() {
+        Ok(metrics) => (
+            StatusCode::OK,
+            [("content-type", "text/plain; version=0.0.4; charset=utf-8")],
+            metrics,
+        )
+            .into_response(),
         Err(e) => {
-            warn!("Failed to collect metrics: {}", e);
+            warn!("Failed to encode metrics: {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Failed to collect metrics",
+                "Failed to encode metrics",
             )
                 .into_response()
         }
     }
 }
 
-/// Handler for the /health endpoint
+/// Handler for the /query endpoint
+///
+/// Returns the most recent Pi-hole queries for a single domain, at most
+/// [`MAX_DOMAIN_QUERY_LENGTH`] of them
+pub async fn domain_query_handler(
+    State(collector): State<Arc<PiholeCollector>>,
+    Query(params): Query<QueryByDomainParams>,
+) -> Response {
+    if !is_valid_domain(&params.domain) {
+        return (StatusCode::BAD_REQUEST, "Invalid domain").into_response();
+    }
+
+    let path = format!(
+        "queries?domain={}&length={}",
+        params.domain,
+        params
+            .length
+            .unwrap_or(DEFAULT_DOMAIN_QUERY_LENGTH)
+            .min(MAX_DOMAIN_QUERY_LENGTH)
+    );
+    let queries = match collector.get_adhoc_api_call(&path).await {
+        Ok(json) => serde_json::from_value::<QueriesResponse>(json),
+        Err(e) => {
+            warn!("Failed to query domain {}: {}", params.domain, e);
+            return (StatusCode::BAD_GATEWAY, "Failed to query Pi-hole").into_response();
+        }
+    };
+
+    match queries {
+        Ok(queries) => Json(queries).into_response(),
+        Err(e) => {
+            warn!("Failed to parse queries for {}: {}", params.domain, e);
+            (StatusCode::BAD_GATEWAY, "Failed to parse Pi-hole response").into_response()
+        }
+    }
+}
+
+/// Handler for the /check endpoint
+///
+/// Returns whether Pi-hole would block a domain, and which kind of list decides it
+pub async fn check_domain_handler(
+    State(collector): State<Arc<PiholeCollector>>,
+    Query(params): Query<CheckDomainParams>,
+) -> Response {
+    if !is_valid_domain(&params.domain) {
+        return (StatusCode::BAD_REQUEST, "Invalid domain").into_response();
+    }
+
+    let path = format!("search/{}?partial=false", params.domain);
+    let search = match collector.get_adhoc_api_call(&path).await {
+        Ok(json) => serde_json::from_value::<SearchResponse>(json),
+        Err(e) => {
+            warn!("Failed to check domain {}: {}", params.domain, e);
+            return (StatusCode::BAD_GATEWAY, "Failed to query Pi-hole").into_response();
+        }
+    };
+
+    match search {
+        Ok(search) => Json(search.search.check()).into_response(),
+        Err(e) => {
+            warn!("Failed to parse search for {}: {}", params.domain, e);
+            (StatusCode::BAD_GATEWAY, "Failed to parse Pi-hole response").into_response()
+        }
+    }
+}
+
+/// Handler for the /debug/pprof/heap endpoint
+///
+/// Returns a gzipped pprof protobuf of sampled live allocations
+#[cfg(feature = "pprof")]
+pub async fn pprof_heap_handler() -> Response {
+    let Some(prof_ctl) = jemalloc_pprof::PROF_CTL.as_ref() else {
+        return (
+            StatusCode::NOT_IMPLEMENTED,
+            "Heap profiling is not available",
+        )
+            .into_response();
+    };
+    let mut prof_ctl = prof_ctl.lock().await;
+    if !prof_ctl.activated() {
+        return (StatusCode::CONFLICT, "Heap profiling is not active").into_response();
+    }
+
+    match prof_ctl.dump_pprof() {
+        Ok(profile) => ([(CONTENT_TYPE, "application/octet-stream")], profile).into_response(),
+        Err(e) => {
+            warn!("Failed to dump heap profile: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to dump heap profile",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Handler for the /debug/pprof/cpu endpoint
+///
+/// Samples the process for `seconds` and returns a pprof protobuf CPU profile
+#[cfg(feature = "pprof")]
+pub async fn pprof_cpu_handler(Query(params): Query<CpuProfileParams>) -> Response {
+    use pprof::protos::Message;
+
+    let seconds = params.seconds.unwrap_or(DEFAULT_CPU_PROFILE_SECS);
+    if !(1..=MAX_CPU_PROFILE_SECS).contains(&seconds) {
+        return (
+            StatusCode::BAD_REQUEST,
+            format!("seconds must be between 1 and {MAX_CPU_PROFILE_SECS}"),
+        )
+            .into_response();
+    }
+
+    let guard = match pprof::ProfilerGuardBuilder::default()
+        .frequency(CPU_PROFILE_FREQUENCY)
+        .blocklist(&["libc", "libgcc", "pthread", "vdso"])
+        .build()
+    {
+        Ok(guard) => guard,
+        Err(pprof::Error::Running) => {
+            return (StatusCode::CONFLICT, "A CPU profile is already running").into_response();
+        }
+        Err(e) => {
+            warn!("Failed to start CPU profile: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to start CPU profile",
+            )
+                .into_response();
+        }
+    };
+    tokio::time::sleep(Duration::from_secs(seconds)).await;
+
+    match guard.report().build().and_then(|report| report.pprof()) {
+        Ok(profile) => (
+            [(CONTENT_TYPE, "application/octet-stream")],
+            profile.encode_to_vec(),
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to build CPU profile: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to build CPU profile",
+            )
+                .into_response()
+        }
+    }
+}
+
+/// Handler for the /metrics/cardinality endpoint
+///
+/// Returns the number of label sets each metric family currently exposes
+pub async fn cardinality_handler(State(collector): State<Arc<PiholeCollector>>) -> Response {
+    Json(collector.metrics.cardinality_report()).into_response()
+}
+
+/// Handler for the /healthz endpoint
+///
+/// Simple health check that returns the configured status and body
+pub async fn health_handler(State(health): State<Arc<HealthResponse>>) -> Response {
+    (health.status, health.body.clone()).into_response()
+}
+
+/// Handler for the /config endpoint
 ///
-/// Simple health check that returns OK
-pub async fn health_handler() -> Response {
-    (StatusCode::OK, "OK").into_response()
+/// Returns the loaded configuration as JSON with secrets redacted
+pub async fn config_handler(State(config): State<Arc<ArgsPublic>>) -> Response {
+    Json(config.as_ref()).into_response()
 }
@@ -5,17 +5,31 @@
 
 pub mod api;
 pub mod args;
+pub mod asn;
+pub mod categorizer;
+#[cfg(feature = "cloudwatch")]
+pub mod cloudwatch;
 pub mod collector;
+pub mod config;
+pub mod consul;
+pub mod disk;
+pub mod error;
 pub mod handlers;
 pub mod metrics;
+pub mod monitor;
+pub mod statsd;
+pub mod telemetry;
+pub mod tls;
 
 // Re-export commonly used types
-pub use args::Args;
+pub use args::{Args, ArgsPublic, Command};
 pub use collector::PiholeCollector;
-pub use handlers::{health_handler, metrics_handler};
+pub use error::PiholeError;
+pub use handlers::{
+    cardinality_handler, check_domain_handler, config_handler, domain_query_handler,
+    health_handler, metrics_changes_handler, metrics_handler, metrics_streaming_handler,
+};
 pub use metrics::PiholeMetrics;
 
-use std::error::Error;
-
 /// Result type used throughout the library
-pub type Result<T> = std::result::Result<T, Box<dyn Error>>;
+pub type Result<T> = std::result::Result<T, PiholeError>;
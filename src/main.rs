@@ -1,37 +1,318 @@
 use axum::{Router, routing::get};
 use clap::Parser;
-use pihole_exporter::{Args, PiholeCollector, health_handler, metrics_handler};
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use tower_http::trace::TraceLayer;
-use tracing::info;
+use pihole_exporter::{
+    Args, ArgsPublic, Command, PiholeCollector, PiholeError, cardinality_handler,
+    check_domain_handler,
+    collector::{ScrapeMode, initial_scrape_delay, next_scrape_delay},
+    config::ProfileConfig,
+    config_handler,
+    consul::ConsulRegistration,
+    domain_query_handler,
+    handlers::HealthResponse,
+    health_handler, metrics_changes_handler, metrics_handler, metrics_streaming_handler, monitor,
+    telemetry,
+};
+use std::{
+    fs, io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, UnixListener},
+    signal::unix::{SignalKind, signal},
+    time::Instant,
+};
+use tower_http::{CompressionLevel, compression::CompressionLayer, trace::TraceLayer};
+use tracing::{info, warn};
+
+/// How often the Pi-hole TLS certificate expiry is checked
+const TLS_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// jemalloc, so /debug/pprof/heap can read its allocation samples
+#[cfg(feature = "pprof")]
+#[global_allocator]
+static ALLOC: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// Sample an allocation every 512KiB, activated at startup by --enable-pprof
+#[cfg(feature = "pprof")]
+#[allow(non_upper_case_globals)]
+#[unsafe(export_name = "malloc_conf")]
+pub static malloc_conf: &[u8] = b"prof:true,prof_active:false,lg_prof_sample:19\0";
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
     // Parse command line arguments
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if let (Some(path), Some(name)) = (&args.profiles_file, args.profile.clone()) {
+        ProfileConfig::load(path)
+            .and_then(|config| config.apply(&name, &mut args))
+            .map_err(|e| e.context(format!("loading profile {name}")))?;
+    }
+
+    if let Some(Command::GenerateMonitor {
+        name,
+        namespace,
+        scrape_interval,
+    }) = &args.command
+    {
+        print!(
+            "{}",
+            monitor::service_monitor_manifest(&args, name, namespace, scrape_interval)
+        );
+        return Ok(());
+    }
+    if let Some(Command::GenerateDashboard {
+        datasource_name,
+        dashboard_title,
+    }) = &args.command
+    {
+        print!(
+            "{}",
+            monitor::grafana_dashboard(datasource_name, dashboard_title)
+        );
+        return Ok(());
+    }
+    if let Some(Command::GenerateRules {
+        job,
+        block_rate_threshold,
+        nxdomain_threshold,
+        rules_output_file,
+    }) = &args.command
+    {
+        let rules = monitor::alert_rules(job, *block_rate_threshold, *nxdomain_threshold);
+        match rules_output_file {
+            Some(path) => fs::write(path, rules)?,
+            None => print!("{rules}"),
+        }
+        return Ok(());
+    }
+
+    // Initialize tracing
+    let tracer_provider = telemetry::init_tracing(args.otlp_endpoint.as_deref())?;
 
     info!("Starting Pi-hole Prometheus exporter");
     info!("Pi-hole host: {}", args.pihole);
+    if args.strip_private_ips {
+        warn!("--strip-private-ips is set, private client addresses are reported as /24 subnets");
+    }
 
     // Create Pi-hole collector
-    let collector = Arc::new(PiholeCollector::new(args.pihole, args.tls, args.password).await?);
+    let collector = Arc::new(PiholeCollector::new(&args).await?);
+
+    // Periodically check the Pi-hole TLS certificate
+    if args.check_tls_expiry {
+        let collector = collector.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(TLS_EXPIRY_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = collector.update_tls_expiry().await {
+                    warn!("Failed to check TLS certificate expiry: {}", e);
+                }
+            }
+        });
+    }
+
+    if let ScrapeMode::Background(period) = collector.scrape_mode {
+        info!("Updating metrics every {}s", period.as_secs());
+        tokio::spawn(background_update_task(
+            collector.clone(),
+            period,
+            Duration::from_secs(args.scrape_jitter_secs),
+        ));
+    }
+
+    if args.password.is_some() && args.session_timeout_secs > 0 {
+        tokio::spawn(session_refresh_task(
+            collector.clone(),
+            collector.session_timeout / 10,
+        ));
+    }
+
+    if args.schema_check_interval_secs > 0 {
+        tokio::spawn(schema_validation_task(
+            collector.clone(),
+            Duration::from_secs(args.schema_check_interval_secs),
+        ));
+    }
 
     // Build the application router
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/metrics", get(metrics_handler))
-        .route("/healthz", get(health_handler))
-        .with_state(collector)
-        .layer(TraceLayer::new_for_http());
+        .route("/metrics/changes", get(metrics_changes_handler))
+        .route("/metrics/stream", get(metrics_streaming_handler))
+        .with_state(collector.clone())
+        .merge(
+            Router::new()
+                .route("/healthz", get(health_handler))
+                .with_state(Arc::new(HealthResponse::from(&args))),
+        );
+
+    if args.enable_cardinality_report {
+        app = app.merge(
+            Router::new()
+                .route("/metrics/cardinality", get(cardinality_handler))
+                .with_state(collector.clone()),
+        );
+    }
+
+    if args.enable_domain_query {
+        app = app.merge(
+            Router::new()
+                .route("/query", get(domain_query_handler))
+                .with_state(collector.clone()),
+        );
+    }
+
+    if args.enable_domain_check {
+        app = app.merge(
+            Router::new()
+                .route("/check", get(check_domain_handler))
+                .with_state(collector),
+        );
+    }
+
+    #[cfg(feature = "pprof")]
+    if args.enable_pprof {
+        if let Some(prof_ctl) = jemalloc_pprof::PROF_CTL.as_ref()
+            && let Err(e) = prof_ctl.lock().await.activate()
+        {
+            warn!("Failed to activate heap profiling: {}", e);
+        }
+        app = app.merge(
+            Router::new()
+                .route(
+                    "/debug/pprof/heap",
+                    get(pihole_exporter::handlers::pprof_heap_handler),
+                )
+                .route(
+                    "/debug/pprof/cpu",
+                    get(pihole_exporter::handlers::pprof_cpu_handler),
+                ),
+        );
+    }
+
+    if args.enable_config_endpoint {
+        app = app.merge(
+            Router::new()
+                .route("/config", get(config_handler))
+                .with_state(Arc::new(ArgsPublic::from(&args))),
+        );
+    }
+
+    if args.enable_compression {
+        app = app.layer(
+            CompressionLayer::new()
+                .quality(CompressionLevel::Precise(args.compression_level as i32)),
+        );
+    }
+    let app = app.layer(TraceLayer::new_for_http());
 
     // Start the server
-    let listener = TcpListener::bind(format!("{}:{}", args.host, args.port)).await?;
-    info!("Server listening on {}", listener.local_addr()?);
+    if let Some(path) = &args.unix_socket {
+        // Remove a stale socket left behind by a previous run, but nothing else
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(PiholeError::Config(format!(
+                    "--unix-socket {} exists and is not a socket",
+                    path.display()
+                ))
+                .into());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let listener = UnixListener::bind(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o660))?;
+        info!("Server listening on {}", path.display());
+
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await?;
+    } else {
+        let listener = TcpListener::bind(format!("{}:{}", args.host, args.port)).await?;
+        info!("Server listening on {}", listener.local_addr()?);
 
-    axum::serve(listener, app).await?;
+        let consul = match &args.consul_url {
+            Some(url) => Some(
+                ConsulRegistration::register(
+                    url,
+                    &args.consul_service_name,
+                    &args.consul_tags,
+                    &args.host,
+                    listener.local_addr()?.port(),
+                )
+                .await?,
+            ),
+            None => None,
+        };
+
+        axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await?;
+
+        if let Some(consul) = consul
+            && let Err(e) = consul.deregister().await
+        {
+            warn!("Failed to deregister from Consul: {}", e);
+        }
+    }
+
+    if let Some(provider) = tracer_provider {
+        provider.shutdown()?;
+    }
 
     Ok(())
 }
+
+/// Update the metrics every `period` for [`ScrapeMode::Background`], shifted by up to `jitter`
+async fn background_update_task(
+    collector: Arc<PiholeCollector>,
+    period: Duration,
+    jitter: Duration,
+) {
+    let mut next = Instant::now() + initial_scrape_delay(jitter);
+    loop {
+        tokio::time::sleep_until(next).await;
+        next += next_scrape_delay(period, jitter);
+        // Scrapes of the stale metrics are recorded when they are served
+        if let Err(e) = collector.update_metrics().await {
+            warn!("Failed to collect metrics: {}", e);
+        }
+    }
+}
+
+/// Renew the Pi-hole session before it expires, checking every `period`
+async fn session_refresh_task(collector: Arc<PiholeCollector>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        match collector.refresh_auth_if_expiring().await {
+            Ok(true) => info!("Renewed the Pi-hole session"),
+            Ok(false) => {}
+            Err(e) => warn!("Failed to renew the Pi-hole session: {}", e),
+        }
+    }
+}
+
+/// Periodically check that the Pi-hole API still returns the fields the exporter reads
+async fn schema_validation_task(collector: Arc<PiholeCollector>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        collector.check_api_schema().await;
+    }
+}
+
+/// Resolve once the process receives SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("installing SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+    info!("Shutting down");
+}
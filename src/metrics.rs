@@ -1,33 +1,275 @@
+use std::{
+    borrow::Cow,
+    fs,
+    hash::Hash,
+    ops::Deref,
+    path::Path,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use ahash::{HashMap, HashSet};
 use prometheus_client::{
-    encoding::EncodeLabelSet,
-    metrics::{family::Family, gauge::Gauge},
-    registry::Registry,
+    encoding::{EncodeLabelSet, EncodeMetric, MetricEncoder},
+    metrics::{
+        MetricType, TypedMetric,
+        counter::Counter,
+        family::Family,
+        gauge::Gauge,
+        histogram::{Histogram, exponential_buckets},
+    },
+    registry::{Metric, Registry, Unit},
 };
 
+use crate::Result;
+
+/// Default number of distinct label sets kept per metric family
+pub const DEFAULT_MAX_LABEL_VALUES: usize = 1000;
+
+/// Default rolling windows, in seconds
+pub const DEFAULT_WINDOW_INTERVALS: [u64; 1] = [60];
+
+/// Label value used for the series that aggregates label sets beyond the limit
+pub const CARDINALITY_LIMIT_LABEL: &str = "__cardinality_limit__";
+
+/// Help text overrides keyed by registered metric name
+///
+/// Names are as registered, without the `_total` or unit suffixes added on exposition.
+pub type MetricDescriptions = HashMap<String, String>;
+
+/// Load help text overrides from a YAML mapping of metric name to description
+pub fn load_metric_descriptions(path: &Path) -> Result<MetricDescriptions> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Languages accepted by `--metric-language`, each with a `src/i18n/<lang>.toml`
+pub const METRIC_LANGUAGES: [&str; 2] = ["en", "de"];
+
+/// Built-in help text for `language`, empty for an unknown language
+///
+/// Metrics missing from a translation keep the English help text passed at registration.
+pub fn builtin_metric_descriptions(language: &str) -> Result<MetricDescriptions> {
+    let contents = match language {
+        "en" => include_str!("i18n/en.toml"),
+        "de" => include_str!("i18n/de.toml"),
+        _ => return Ok(MetricDescriptions::default()),
+    };
+    Ok(toml::from_str(contents)?)
+}
+
+/// Placeholder for the window suffix in window metric names and descriptions
+pub const WINDOW_PLACEHOLDER: &str = "{window}";
+
+/// Constant labels added to every series, as passed to [`Registry::with_labels`]
+pub type BaseLabels = [(Cow<'static, str>, Cow<'static, str>)];
+
+/// Where a [`DescribedRegistry`] registers metrics
+enum RegistryTarget<'a> {
+    Single(&'a mut Registry),
+    /// A new registry with `labels` for each metric family, so families can be encoded one at a time
+    PerFamily {
+        registries: &'a mut Vec<Registry>,
+        labels: &'a BaseLabels,
+    },
+}
+
+/// Registry wrapper that swaps in help text from [`MetricDescriptions`]
+pub struct DescribedRegistry<'a> {
+    target: RegistryTarget<'a>,
+    descriptions: &'a MetricDescriptions,
+    /// Suffix of the window metrics being registered, see [`WindowMetrics::suffix`]
+    window: Option<String>,
+    /// Registered names that had no description
+    missing: Vec<String>,
+}
+
+impl<'a> DescribedRegistry<'a> {
+    fn new(registry: &'a mut Registry, descriptions: &'a MetricDescriptions) -> Self {
+        Self {
+            target: RegistryTarget::Single(registry),
+            descriptions,
+            window: None,
+            missing: Vec::new(),
+        }
+    }
+
+    /// Register each metric family into its own registry appended to `registries`
+    ///
+    /// Encoding them in order with [`prometheus_client::encoding::text::encode_registry`]
+    /// matches encoding a single registry holding every family.
+    pub fn per_family(
+        registries: &'a mut Vec<Registry>,
+        labels: &'a BaseLabels,
+        descriptions: &'a MetricDescriptions,
+    ) -> Self {
+        Self {
+            target: RegistryTarget::PerFamily { registries, labels },
+            descriptions,
+            window: None,
+            missing: Vec::new(),
+        }
+    }
+
+    /// Registry for the next metric family
+    fn target(&mut self) -> &mut Registry {
+        match &mut self.target {
+            RegistryTarget::Single(registry) => registry,
+            RegistryTarget::PerFamily { registries, labels } => {
+                registries.push(Registry::with_labels(labels.iter().cloned()));
+                registries.last_mut().expect("just pushed")
+            }
+        }
+    }
+
+    /// Help text for `name`, preferring the configured description
+    ///
+    /// Window metrics also match a description keyed by the name with its
+    /// suffix replaced by [`WINDOW_PLACEHOLDER`], e.g. `pihole_query_type_{window}`.
+    fn help(&mut self, name: &str, help: String) -> String {
+        if let Some(description) = self.descriptions.get(name) {
+            return description.clone();
+        }
+        if let Some(window) = &self.window {
+            let template = name
+                .strip_suffix(window.as_str())
+                .map(|prefix| format!("{prefix}{WINDOW_PLACEHOLDER}"));
+            if let Some(description) = template.and_then(|name| self.descriptions.get(&name)) {
+                return description.replace(WINDOW_PLACEHOLDER, window);
+            }
+        }
+        self.missing.push(name.to_string());
+        help
+    }
+
+    /// Register a metric, see [`Registry::register`]
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        help: impl Into<String>,
+        metric: impl Metric,
+    ) {
+        let name = name.into();
+        let help = self.help(&name, help.into());
+        self.target().register(name, help, metric);
+    }
+
+    /// Register a metric with a unit, see [`Registry::register_with_unit`]
+    pub fn register_with_unit(
+        &mut self,
+        name: impl Into<String>,
+        help: impl Into<String>,
+        unit: Unit,
+        metric: impl Metric,
+    ) {
+        let name = name.into();
+        let help = self.help(&name, help.into());
+        self.target().register_with_unit(name, help, unit, metric);
+    }
+}
+
+/// Label sets that can be collapsed into a single overflow series
+pub trait OverflowLabels {
+    /// Label set used for values beyond the cardinality limit
+    fn overflow() -> Self;
+}
+
+/// Set `gauge` to `value`, skipping the store when it already holds that value
+pub fn set_if_changed(gauge: &Gauge, value: i64) {
+    if gauge.get() != value {
+        gauge.set(value);
+    }
+}
+
 /// Labels for query type metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct QueryTypeLabels {
     pub query_type: String,
 }
 
+impl OverflowLabels for QueryTypeLabels {
+    fn overflow() -> Self {
+        Self {
+            query_type: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
 /// Labels for query status metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct QueryStatusLabels {
     pub query_status: String,
 }
 
+impl OverflowLabels for QueryStatusLabels {
+    fn overflow() -> Self {
+        Self {
+            query_status: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
 /// Labels for reply type metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct ReplyTypeLabels {
     pub reply_type: String,
 }
 
+impl OverflowLabels for ReplyTypeLabels {
+    fn overflow() -> Self {
+        Self {
+            reply_type: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for the share of an upstream's answers served from cache or fetched
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct UpstreamCacheLabels {
+    pub upstream: String,
+    /// `cache` or `upstream`
+    pub served_from: String,
+}
+
+impl OverflowLabels for UpstreamCacheLabels {
+    fn overflow() -> Self {
+        Self {
+            upstream: CARDINALITY_LIMIT_LABEL.to_string(),
+            served_from: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for DNS response code metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct RcodeLabels {
+    pub rcode: String,
+}
+
+impl OverflowLabels for RcodeLabels {
+    fn overflow() -> Self {
+        Self {
+            rcode: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
 /// Labels for category-based metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct CategoryLabels {
     pub category: String,
 }
 
+impl OverflowLabels for CategoryLabels {
+    fn overflow() -> Self {
+        Self {
+            category: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
 /// Labels for upstream server metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct UpstreamLabels {
@@ -36,59 +278,872 @@ pub struct UpstreamLabels {
     pub port: String,
 }
 
+impl OverflowLabels for UpstreamLabels {
+    fn overflow() -> Self {
+        Self {
+            ip: CARDINALITY_LIMIT_LABEL.to_string(),
+            name: CARDINALITY_LIMIT_LABEL.to_string(),
+            port: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for window query counts per client and query type
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ClientTypeLabels {
+    pub client: String,
+    pub query_type: String,
+}
+
+impl OverflowLabels for ClientTypeLabels {
+    fn overflow() -> Self {
+        Self {
+            client: CARDINALITY_LIMIT_LABEL.to_string(),
+            query_type: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for the autonomous system of a querying client
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct AsnLabels {
+    pub asn: String,
+    pub org: String,
+}
+
+impl OverflowLabels for AsnLabels {
+    fn overflow() -> Self {
+        Self {
+            asn: CARDINALITY_LIMIT_LABEL.to_string(),
+            org: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for upstream traffic by IP version
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct IpVersionLabels {
+    pub version: String,
+}
+
+impl OverflowLabels for IpVersionLabels {
+    fn overflow() -> Self {
+        Self {
+            version: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for client group metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct GroupLabels {
+    pub group_id: String,
+    pub group_name: String,
+}
+
+impl OverflowLabels for GroupLabels {
+    fn overflow() -> Self {
+        Self {
+            group_id: CARDINALITY_LIMIT_LABEL.to_string(),
+            group_name: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for blocklist metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct BlocklistLabels {
+    pub id: String,
+    pub address: String,
+}
+
+impl OverflowLabels for BlocklistLabels {
+    fn overflow() -> Self {
+        Self {
+            id: CARDINALITY_LIMIT_LABEL.to_string(),
+            address: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for historical metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct HistoricalLabels {
+    pub period: String,
+}
+
+impl OverflowLabels for HistoricalLabels {
+    fn overflow() -> Self {
+        Self {
+            period: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for in-flight query metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct InFlightLabels {
+    pub synthetic: String,
+}
+
+impl OverflowLabels for InFlightLabels {
+    fn overflow() -> Self {
+        Self {
+            synthetic: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for per-window bookkeeping metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct WindowLabels {
+    pub window: String,
+}
+
+impl OverflowLabels for WindowLabels {
+    fn overflow() -> Self {
+        Self {
+            window: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
 /// Labels for client metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct ClientLabels {
     pub query_client: String,
 }
 
+impl OverflowLabels for ClientLabels {
+    fn overflow() -> Self {
+        Self {
+            query_client: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
 /// Labels for upstream count metrics
 #[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
 pub struct UpstreamCountLabels {
     pub query_upstream: String,
 }
 
+impl OverflowLabels for UpstreamCountLabels {
+    fn overflow() -> Self {
+        Self {
+            query_upstream: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for DHCP lease metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct DhcpLeaseLabels {
+    pub ip: String,
+    pub hostname: String,
+}
+
+impl OverflowLabels for DhcpLeaseLabels {
+    fn overflow() -> Self {
+        Self {
+            ip: CARDINALITY_LIMIT_LABEL.to_string(),
+            hostname: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for per-endpoint API timing metrics
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ApiEndpointLabels {
+    pub endpoint: String,
+}
+
+impl OverflowLabels for ApiEndpointLabels {
+    fn overflow() -> Self {
+        Self {
+            endpoint: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for the detected Pi-hole API version
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct ApiVersionLabels {
+    pub major: String,
+    pub minor: String,
+    pub patch: String,
+}
+
+impl OverflowLabels for ApiVersionLabels {
+    fn overflow() -> Self {
+        Self {
+            major: CARDINALITY_LIMIT_LABEL.to_string(),
+            minor: CARDINALITY_LIMIT_LABEL.to_string(),
+            patch: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for how the exporter authenticates with Pi-hole
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct AuthMethodLabels {
+    pub method: String,
+}
+
+impl OverflowLabels for AuthMethodLabels {
+    fn overflow() -> Self {
+        Self {
+            method: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Labels for why a query was blocked
+#[derive(Clone, Debug, Hash, PartialEq, Eq, EncodeLabelSet)]
+pub struct BlockReasonLabels {
+    pub reason: String,
+}
+
+impl OverflowLabels for BlockReasonLabels {
+    fn overflow() -> Self {
+        Self {
+            reason: CARDINALITY_LIMIT_LABEL.to_string(),
+        }
+    }
+}
+
+/// Distinct label sets seen by a [`CardinalityLimitedFamily`]
+#[derive(Debug)]
+struct CardinalityState<L> {
+    seen: HashSet<L>,
+    /// Values summed into the overflow series, for the update cycle in `cycle`
+    overflow: HashMap<L, i64>,
+    cycle: u64,
+}
+
+/// Metric family that caps the number of distinct label sets it exposes
+///
+/// Once `limit` label sets have been seen, any new label set is redirected to
+/// the [`OverflowLabels::overflow`] series and counted in the shared
+/// `pihole_cardinality_limit_hits_total` counter. The overflow series only
+/// sums label sets written since the shared update cycle last advanced.
+#[derive(Debug)]
+pub struct CardinalityLimitedFamily<L, M> {
+    family: Family<L, M>,
+    state: Arc<Mutex<CardinalityState<L>>>,
+    limit: usize,
+    hits: Counter,
+    cycle: Arc<AtomicU64>,
+}
+
+impl<L, M> Clone for CardinalityLimitedFamily<L, M> {
+    fn clone(&self) -> Self {
+        Self {
+            family: self.family.clone(),
+            state: self.state.clone(),
+            limit: self.limit,
+            hits: self.hits.clone(),
+            cycle: self.cycle.clone(),
+        }
+    }
+}
+
+impl<L, M> CardinalityLimitedFamily<L, M>
+where
+    L: Clone + Hash + Eq + OverflowLabels,
+    M: Default,
+{
+    /// Create a new family that keeps at most `limit` label sets
+    ///
+    /// `cycle` is the shared update cycle, see [`PiholeMetrics::start_update_cycle`].
+    pub fn new(limit: usize, hits: Counter, cycle: Arc<AtomicU64>) -> Self {
+        Self {
+            family: Family::default(),
+            state: Arc::new(Mutex::new(CardinalityState {
+                seen: HashSet::default(),
+                overflow: HashMap::default(),
+                cycle: cycle.load(Ordering::Relaxed),
+            })),
+            limit,
+            hits,
+            cycle,
+        }
+    }
+
+    /// Whether `labels` fits within the limit, recording it if so
+    fn admit(&self, state: &mut CardinalityState<L>, labels: &L) -> bool {
+        if state.seen.contains(labels) {
+            return true;
+        }
+        if state.seen.len() < self.limit {
+            state.seen.insert(labels.clone());
+            return true;
+        }
+        self.hits.inc();
+        false
+    }
+
+    /// Access the metric for `labels`, or the overflow metric past the limit
+    pub fn get_or_create(&self, labels: &L) -> impl Deref<Target = M> + '_ {
+        let mut state = self.state.lock().unwrap();
+        if self.admit(&mut state, labels) {
+            self.family.get_or_create(labels)
+        } else {
+            self.family.get_or_create(&L::overflow())
+        }
+    }
+
+    /// Number of label sets currently exposed, including the overflow series
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        let overflow = L::overflow();
+        let has_overflow = !state.seen.contains(&overflow) && self.family.get(&overflow).is_some();
+        state.seen.len() + usize::from(has_overflow)
+    }
+
+    /// Whether no label set has been exposed yet
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Remove every series, for families whose label sets come and go between scrapes
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.seen.clear();
+        state.overflow.clear();
+        self.family.clear();
+    }
+}
+
+impl<L> CardinalityLimitedFamily<L, Gauge>
+where
+    L: Clone + Hash + Eq + OverflowLabels,
+{
+    /// Set the gauge for `labels`
+    ///
+    /// Label sets beyond the limit are summed into the overflow series.
+    /// The gauge is only written when the value changes.
+    pub fn set(&self, labels: &L, value: i64) {
+        let mut state = self.state.lock().unwrap();
+        if self.admit(&mut state, labels) {
+            set_if_changed(&self.family.get_or_create(labels), value);
+        } else {
+            let cycle = self.cycle.load(Ordering::Relaxed);
+            if state.cycle != cycle {
+                state.overflow.clear();
+                state.cycle = cycle;
+            }
+            state.overflow.insert(labels.clone(), value);
+            let total = state.overflow.values().sum();
+            set_if_changed(&self.family.get_or_create(&L::overflow()), total);
+        }
+    }
+
+    /// Current value for `labels`, if that series exists
+    pub fn get(&self, labels: &L) -> Option<i64> {
+        self.family.get(labels).map(|gauge| gauge.get())
+    }
+}
+
+impl<L, M: TypedMetric> TypedMetric for CardinalityLimitedFamily<L, M> {
+    const TYPE: MetricType = <M as TypedMetric>::TYPE;
+}
+
+impl<L, M> EncodeMetric for CardinalityLimitedFamily<L, M>
+where
+    L: Clone + Hash + Eq + EncodeLabelSet,
+    M: EncodeMetric + TypedMetric + Default,
+{
+    fn encode(&self, encoder: MetricEncoder) -> std::result::Result<(), std::fmt::Error> {
+        self.family.encode(encoder)
+    }
+
+    fn metric_type(&self) -> MetricType {
+        M::TYPE
+    }
+}
+
+/// Query metrics computed over a trailing time window
+#[derive(Debug)]
+pub struct WindowMetrics {
+    pub interval_secs: u64,
+    pub query_type: CardinalityLimitedFamily<QueryTypeLabels, Gauge>,
+    pub query_status: CardinalityLimitedFamily<QueryStatusLabels, Gauge>,
+    pub query_reply: CardinalityLimitedFamily<ReplyTypeLabels, Gauge>,
+    pub query_rcode: CardinalityLimitedFamily<RcodeLabels, Gauge>,
+    pub query_client: CardinalityLimitedFamily<ClientLabels, Gauge>,
+    pub query_client_asn: CardinalityLimitedFamily<AsnLabels, Gauge>,
+    pub query_client_type: CardinalityLimitedFamily<ClientTypeLabels, Gauge>,
+    pub query_category: CardinalityLimitedFamily<CategoryLabels, Gauge>,
+    pub query_upstream: CardinalityLimitedFamily<UpstreamCountLabels, Gauge>,
+    pub upstream_cache_ratio: CardinalityLimitedFamily<UpstreamCacheLabels, Gauge<f64, AtomicU64>>,
+    pub doh_queries: Gauge,
+    pub potential_dns_rebinding: Gauge,
+    pub special_domain_queries: Gauge,
+    pub special_domain_types: CardinalityLimitedFamily<QueryTypeLabels, Gauge>,
+    pub nxdomain: Gauge,
+    pub nxdomain_rate: Gauge<f64, AtomicU64>,
+    pub cache_hit_rate: Gauge<f64, AtomicU64>,
+    pub blocked_reason: CardinalityLimitedFamily<BlockReasonLabels, Gauge>,
+}
+
+impl WindowMetrics {
+    /// Create the metrics for a window of `interval_secs` seconds
+    pub fn new(interval_secs: u64, limit: usize, hits: &Counter, cycle: &Arc<AtomicU64>) -> Self {
+        Self {
+            interval_secs,
+            query_type: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_status: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_reply: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_rcode: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_client: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_client_asn: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_client_type: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_category: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_upstream: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            upstream_cache_ratio: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            doh_queries: Gauge::default(),
+            potential_dns_rebinding: Gauge::default(),
+            special_domain_queries: Gauge::default(),
+            special_domain_types: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            nxdomain: Gauge::default(),
+            nxdomain_rate: Gauge::default(),
+            cache_hit_rate: Gauge::default(),
+            blocked_reason: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+        }
+    }
+
+    /// Remove every labelled series, so label sets missing from the next window disappear
+    pub fn clear(&self) {
+        self.query_type.clear();
+        self.query_status.clear();
+        self.query_reply.clear();
+        self.query_rcode.clear();
+        self.query_client.clear();
+        self.query_client_asn.clear();
+        self.query_client_type.clear();
+        self.query_category.clear();
+        self.query_upstream.clear();
+        self.upstream_cache_ratio.clear();
+        self.special_domain_types.clear();
+        self.blocked_reason.clear();
+    }
+
+    /// Metric name suffix for this window, e.g. `1m`, `1h` or `90s`
+    pub fn suffix(&self) -> String {
+        match self.interval_secs {
+            secs if secs % 3600 == 0 => format!("{}h", secs / 3600),
+            secs if secs % 60 == 0 => format!("{}m", secs / 60),
+            secs => format!("{secs}s"),
+        }
+    }
+
+    /// Label set counts of the window families, keyed by metric name
+    pub fn cardinality_report(&self, report: &mut HashMap<String, usize>) {
+        let suffix = self.suffix();
+        for (name, len) in [
+            ("query_type", self.query_type.len()),
+            ("query_status", self.query_status.len()),
+            ("query_reply", self.query_reply.len()),
+            ("query_rcode", self.query_rcode.len()),
+            ("query_client", self.query_client.len()),
+            ("query_client_asn", self.query_client_asn.len()),
+            ("query_client_type", self.query_client_type.len()),
+            ("query_category", self.query_category.len()),
+            ("query_upstream", self.query_upstream.len()),
+            ("upstream_cache_ratio", self.upstream_cache_ratio.len()),
+            ("special_domain_types", self.special_domain_types.len()),
+            ("blocked_query_reason", self.blocked_reason.len()),
+        ] {
+            report.insert(format!("pihole_{name}_{suffix}"), len);
+        }
+    }
+
+    /// Register the window metrics with the provided registry
+    pub fn register(&self, registry: &mut DescribedRegistry) {
+        let suffix = self.suffix();
+        registry.window = Some(suffix.clone());
+        registry.register(
+            format!("pihole_query_type_{suffix}"),
+            format!("Count of query types (last whole {suffix})"),
+            self.query_type.clone(),
+        );
+        registry.register(
+            format!("pihole_query_status_{suffix}"),
+            format!("Count of query status (last whole {suffix})"),
+            self.query_status.clone(),
+        );
+        registry.register(
+            format!("pihole_query_reply_{suffix}"),
+            format!("Count of query reply types (last whole {suffix})"),
+            self.query_reply.clone(),
+        );
+        registry.register(
+            format!("pihole_query_rcode_{suffix}"),
+            format!("Count of reply DNS response codes (last whole {suffix})"),
+            self.query_rcode.clone(),
+        );
+        registry.register(
+            format!("pihole_query_client_{suffix}"),
+            format!("Count of query clients (last whole {suffix})"),
+            self.query_client.clone(),
+        );
+        registry.register(
+            format!("pihole_query_client_asn_{suffix}"),
+            format!("Count of queries by client autonomous system (last whole {suffix})"),
+            self.query_client_asn.clone(),
+        );
+        registry.register(
+            format!("pihole_query_client_type_{suffix}"),
+            format!("Count of query types per client (last whole {suffix})"),
+            self.query_client_type.clone(),
+        );
+        registry.register(
+            format!("pihole_query_category_{suffix}"),
+            format!("Count of queries by domain category (last whole {suffix})"),
+            self.query_category.clone(),
+        );
+        registry.register(
+            format!("pihole_query_upstream_{suffix}"),
+            format!("Count of query upstream destinations (last whole {suffix})"),
+            self.query_upstream.clone(),
+        );
+        registry.register(
+            format!("pihole_upstream_cache_ratio_{suffix}"),
+            format!("Share of each upstream's answers served from cache or fetched (last whole {suffix})"),
+            self.upstream_cache_ratio.clone(),
+        );
+        registry.register(
+            format!("pihole_doh_queries_{suffix}"),
+            format!("Count of DNS-over-HTTPS queries (last whole {suffix})"),
+            self.doh_queries.clone(),
+        );
+        registry.register(
+            format!("pihole_potential_dns_rebinding_{suffix}"),
+            format!("Count of replies pointing at RFC 1918 addresses (last whole {suffix})"),
+            self.potential_dns_rebinding.clone(),
+        );
+        registry.register(
+            format!("pihole_special_domain_queries_{suffix}"),
+            format!(
+                "Count of queries FTL answered itself as special domains (last whole {suffix})"
+            ),
+            self.special_domain_queries.clone(),
+        );
+        registry.register(
+            format!("pihole_special_domain_types_{suffix}"),
+            format!(
+                "Count of query types FTL answered itself as special domains (last whole {suffix})"
+            ),
+            self.special_domain_types.clone(),
+        );
+        registry.register(
+            format!("pihole_nxdomain_{suffix}"),
+            format!("Count of NXDOMAIN replies (last whole {suffix})"),
+            self.nxdomain.clone(),
+        );
+        registry.register(
+            format!("pihole_nxdomain_rate_{suffix}"),
+            format!("Fraction of queries answered with NXDOMAIN (last whole {suffix})"),
+            self.nxdomain_rate.clone(),
+        );
+        registry.register(
+            format!("pihole_cache_hit_rate_{suffix}"),
+            format!(
+                "Fraction of cached or forwarded queries answered from cache (last whole {suffix})"
+            ),
+            self.cache_hit_rate.clone(),
+        );
+        registry.register(
+            format!("pihole_blocked_query_reason_{suffix}"),
+            format!("Count of blocked queries by blocking reason (last whole {suffix})"),
+            self.blocked_reason.clone(),
+        );
+        registry.window = None;
+    }
+}
+
 /// Container for all Pi-hole Prometheus metrics
 #[derive(Debug)]
 pub struct PiholeMetrics {
     // 24h metrics
-    pub query_by_type: Family<QueryTypeLabels, Gauge>,
-    pub query_by_status: Family<QueryStatusLabels, Gauge>,
-    pub query_replies: Family<ReplyTypeLabels, Gauge>,
-    pub query_count: Family<CategoryLabels, Gauge>,
-    pub client_count: Family<CategoryLabels, Gauge>,
+    pub query_by_type: CardinalityLimitedFamily<QueryTypeLabels, Gauge>,
+    pub query_by_status: CardinalityLimitedFamily<QueryStatusLabels, Gauge>,
+    pub query_replies: CardinalityLimitedFamily<ReplyTypeLabels, Gauge>,
+    pub query_rcode_24h: CardinalityLimitedFamily<RcodeLabels, Gauge>,
+    pub query_count: CardinalityLimitedFamily<CategoryLabels, Gauge>,
+    pub client_count: CardinalityLimitedFamily<CategoryLabels, Gauge>,
     pub domains_being_blocked: Gauge,
-    pub query_upstream_count: Family<UpstreamLabels, Gauge>,
+    pub config_changed: Counter,
+    pub gravity_last_update: Gauge,
+    pub gravity_age: Gauge,
+    pub nxdomain_24h: Gauge,
+    pub nxdomain_rate_24h: Gauge<f64, AtomicU64>,
+    pub cache_hit_rate_24h: Gauge<f64, AtomicU64>,
+    pub query_upstream_count: CardinalityLimitedFamily<UpstreamLabels, Gauge>,
+    pub upstream_count_24h: Gauge,
+    pub upstream_query_entropy: Gauge<f64, AtomicU64>,
+    pub upstream_count_active: Gauge,
+    pub upstream_ip_version_count: CardinalityLimitedFamily<IpVersionLabels, Gauge>,
+    pub group_domains_blocked: CardinalityLimitedFamily<GroupLabels, Gauge>,
+    pub blocklist_last_update: CardinalityLimitedFamily<BlocklistLabels, Gauge>,
+    pub blocklist_invalid_domains: CardinalityLimitedFamily<BlocklistLabels, Gauge>,
+    pub queries_in_flight: CardinalityLimitedFamily<InFlightLabels, Gauge>,
+
+    // Version metrics
+    pub api_version_detected: Gauge,
+    pub api_version_info: CardinalityLimitedFamily<ApiVersionLabels, Gauge>,
+
+    // Auth metrics
+    pub auth_method_info: CardinalityLimitedFamily<AuthMethodLabels, Gauge>,
+    pub auth_session_age: Gauge,
+
+    // Audit metrics
+    pub audit_events: Counter,
+    pub audit_last_event_timestamp: Gauge,
+    pub custom_allow_list_size: Gauge,
+    pub custom_deny_list_size: Gauge,
+
+    // DHCP metrics
+    pub dhcp_enabled: Gauge,
+    pub dhcp_leases: Gauge,
+    pub dhcp_lease_expiry: CardinalityLimitedFamily<DhcpLeaseLabels, Gauge>,
+
+    // FTL process metrics
+    pub ftl_cpu_usage_percent: Gauge<f64, AtomicU64>,
+    pub ftl_memory_usage: Gauge,
+
+    // Long-term database metrics
+    pub database_queries: Gauge,
+    pub database_earliest_query: Gauge,
+
+    // Conditional forwarding metrics
+    pub conditional_forwarding_enabled: Gauge,
+    pub conditional_forwarding_targets: Gauge,
 
-    // 1m metrics
-    pub query_type_1m: Family<QueryTypeLabels, Gauge>,
-    pub query_status_1m: Family<QueryStatusLabels, Gauge>,
-    pub query_reply_1m: Family<ReplyTypeLabels, Gauge>,
-    pub query_client_1m: Family<ClientLabels, Gauge>,
-    pub query_upstream_1m: Family<UpstreamCountLabels, Gauge>,
+    // Rolling window metrics
+    pub windows: Vec<WindowMetrics>,
+    pub queries_in_window: CardinalityLimitedFamily<WindowLabels, Gauge>,
+    pub query_sample_rate: Gauge<f64, AtomicU64>,
+    pub queries_truncated: CardinalityLimitedFamily<WindowLabels, Gauge>,
+
+    // Historical metrics
+    pub historical_queries: CardinalityLimitedFamily<HistoricalLabels, Gauge>,
+    pub historical_blocked: CardinalityLimitedFamily<HistoricalLabels, Gauge>,
+
+    // TLS metrics
+    pub tls_cert_expiry: Gauge,
+
+    // Disk metrics
+    pub disk_usage: Gauge,
+    pub disk_free: Gauge,
+    pub disk_usage_percent: Gauge<f64, AtomicU64>,
+
+    // API schema metrics
+    pub api_schema_valid: Gauge,
+    pub api_schema_check_errors: Counter,
+
+    // Exporter metrics
+    pub api_response_time: CardinalityLimitedFamily<ApiEndpointLabels, Gauge<f64, AtomicU64>>,
+    pub api_response_time_avg: CardinalityLimitedFamily<ApiEndpointLabels, Gauge<f64, AtomicU64>>,
+    pub cardinality_limit_hits: Counter,
+    update_cycle: Arc<AtomicU64>,
+    pub duplicate_queries_skipped: Counter,
+    pub watchdog_restarts: Counter,
+    pub stale_scrapes: Counter,
+    pub stale_scrape_age: Gauge,
+    pub last_error_type: Gauge,
 }
 
 impl PiholeMetrics {
     /// Create a new instance of PiholeMetrics
     pub fn new() -> Self {
+        Self::with_config(DEFAULT_MAX_LABEL_VALUES, &DEFAULT_WINDOW_INTERVALS)
+    }
+
+    /// Create a new instance of PiholeMetrics
+    ///
+    /// Each family keeps at most `limit` label sets, and one set of window
+    /// metrics is created per distinct entry in `window_intervals` (seconds).
+    pub fn with_config(limit: usize, window_intervals: &[u64]) -> Self {
+        let hits = Counter::default();
+        let cycle = Arc::new(AtomicU64::new(0));
+        let mut intervals = window_intervals.to_vec();
+        intervals.sort_unstable();
+        intervals.dedup();
+
         Self {
-            query_by_type: Family::default(),
-            query_by_status: Family::default(),
-            query_replies: Family::default(),
-            query_count: Family::default(),
-            client_count: Family::default(),
+            query_by_type: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_by_status: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_replies: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_rcode_24h: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_count: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            client_count: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
             domains_being_blocked: Gauge::default(),
-            query_upstream_count: Family::default(),
-            query_type_1m: Family::default(),
-            query_status_1m: Family::default(),
-            query_reply_1m: Family::default(),
-            query_client_1m: Family::default(),
-            query_upstream_1m: Family::default(),
+            config_changed: Counter::default(),
+            gravity_last_update: Gauge::default(),
+            gravity_age: Gauge::default(),
+            nxdomain_24h: Gauge::default(),
+            nxdomain_rate_24h: Gauge::default(),
+            cache_hit_rate_24h: Gauge::default(),
+            query_upstream_count: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            upstream_count_24h: Gauge::default(),
+            upstream_query_entropy: Gauge::default(),
+            upstream_count_active: Gauge::default(),
+            upstream_ip_version_count: CardinalityLimitedFamily::new(
+                limit,
+                hits.clone(),
+                cycle.clone(),
+            ),
+            group_domains_blocked: CardinalityLimitedFamily::new(
+                limit,
+                hits.clone(),
+                cycle.clone(),
+            ),
+            blocklist_last_update: CardinalityLimitedFamily::new(
+                limit,
+                hits.clone(),
+                cycle.clone(),
+            ),
+            blocklist_invalid_domains: CardinalityLimitedFamily::new(
+                limit,
+                hits.clone(),
+                cycle.clone(),
+            ),
+            queries_in_flight: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            api_version_detected: Gauge::default(),
+            api_version_info: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            auth_method_info: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            auth_session_age: Gauge::default(),
+            audit_events: Counter::default(),
+            audit_last_event_timestamp: Gauge::default(),
+            custom_allow_list_size: Gauge::default(),
+            custom_deny_list_size: Gauge::default(),
+            dhcp_enabled: Gauge::default(),
+            ftl_cpu_usage_percent: Gauge::default(),
+            ftl_memory_usage: Gauge::default(),
+            database_queries: Gauge::default(),
+            database_earliest_query: Gauge::default(),
+            conditional_forwarding_enabled: Gauge::default(),
+            conditional_forwarding_targets: Gauge::default(),
+            dhcp_leases: Gauge::default(),
+            dhcp_lease_expiry: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            windows: intervals
+                .into_iter()
+                .map(|interval_secs| WindowMetrics::new(interval_secs, limit, &hits, &cycle))
+                .collect(),
+            queries_in_window: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            query_sample_rate: Gauge::default(),
+            queries_truncated: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            historical_queries: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            historical_blocked: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            tls_cert_expiry: Gauge::default(),
+            disk_usage: Gauge::default(),
+            disk_free: Gauge::default(),
+            disk_usage_percent: Gauge::default(),
+            api_schema_valid: Gauge::default(),
+            api_schema_check_errors: Counter::default(),
+            api_response_time: CardinalityLimitedFamily::new(limit, hits.clone(), cycle.clone()),
+            api_response_time_avg: CardinalityLimitedFamily::new(
+                limit,
+                hits.clone(),
+                cycle.clone(),
+            ),
+            cardinality_limit_hits: hits,
+            update_cycle: cycle,
+            duplicate_queries_skipped: Counter::default(),
+            watchdog_restarts: Counter::default(),
+            stale_scrapes: Counter::default(),
+            stale_scrape_age: Gauge::default(),
+            last_error_type: Gauge::default(),
+        }
+    }
+
+    /// Start a new update cycle, so overflow series drop label sets that are no longer written
+    pub fn start_update_cycle(&self) {
+        self.update_cycle.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Number of label sets currently exposed by each labelled family, keyed by metric name
+    pub fn cardinality_report(&self) -> HashMap<String, usize> {
+        let mut report: HashMap<String, usize> = [
+            ("pihole_query_by_type", self.query_by_type.len()),
+            ("pihole_query_by_status", self.query_by_status.len()),
+            ("pihole_query_replies", self.query_replies.len()),
+            ("pihole_query_rcode_24h", self.query_rcode_24h.len()),
+            ("pihole_query_count", self.query_count.len()),
+            ("pihole_client_count", self.client_count.len()),
+            (
+                "pihole_query_upstream_count",
+                self.query_upstream_count.len(),
+            ),
+            (
+                "pihole_upstream_ip_version_count",
+                self.upstream_ip_version_count.len(),
+            ),
+            (
+                "pihole_group_domains_blocked",
+                self.group_domains_blocked.len(),
+            ),
+            (
+                "pihole_blocklist_last_update",
+                self.blocklist_last_update.len(),
+            ),
+            (
+                "pihole_blocklist_invalid_domains",
+                self.blocklist_invalid_domains.len(),
+            ),
+            ("pihole_queries_in_flight", self.queries_in_flight.len()),
+            ("pihole_api_version_info", self.api_version_info.len()),
+            ("pihole_auth_method_info", self.auth_method_info.len()),
+            (
+                "pihole_dhcp_lease_expiry_seconds",
+                self.dhcp_lease_expiry.len(),
+            ),
+            ("pihole_queries_in_window", self.queries_in_window.len()),
+            ("pihole_queries_truncated", self.queries_truncated.len()),
+            (
+                "pihole_historical_queries_total",
+                self.historical_queries.len(),
+            ),
+            (
+                "pihole_historical_blocked_total",
+                self.historical_blocked.len(),
+            ),
+            (
+                "pihole_api_response_time_seconds",
+                self.api_response_time.len(),
+            ),
+            (
+                "pihole_api_response_time_avg_seconds",
+                self.api_response_time_avg.len(),
+            ),
+        ]
+        .into_iter()
+        .map(|(name, len)| (name.to_string(), len))
+        .collect();
+        for window in &self.windows {
+            window.cardinality_report(&mut report);
         }
+        report
     }
 
     /// Register all metrics with the provided registry
     pub fn register(&self, registry: &mut Registry) {
+        self.register_with_descriptions(registry, &MetricDescriptions::default());
+    }
+
+    /// Register all metrics, taking help text from `descriptions` where present
+    pub fn register_with_descriptions(
+        &self,
+        registry: &mut Registry,
+        descriptions: &MetricDescriptions,
+    ) {
+        self.register_described(&mut DescribedRegistry::new(registry, descriptions));
+    }
+
+    /// Register all metrics with a [`DescribedRegistry`]
+    pub fn register_described(&self, registry: &mut DescribedRegistry) {
         registry.register(
             "pihole_query_by_type",
             "Count of queries by type (24h)",
@@ -104,6 +1159,11 @@ impl PiholeMetrics {
             "Count of replies by type over 24h",
             self.query_replies.clone(),
         );
+        registry.register(
+            "pihole_query_rcode_24h",
+            "Count of reply DNS response codes over 24h",
+            self.query_rcode_24h.clone(),
+        );
         registry.register(
             "pihole_query_count",
             "Query counts by category, 24h",
@@ -119,39 +1179,346 @@ impl PiholeMetrics {
             "Number of domains on current blocklist",
             self.domains_being_blocked.clone(),
         );
+        registry.register(
+            "pihole_config_changed",
+            "Times the gravity last update timestamp changed between scrapes",
+            self.config_changed.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_gravity_last_update_timestamp",
+            "Unix time gravity was last rebuilt",
+            Unit::Seconds,
+            self.gravity_last_update.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_gravity_age",
+            "Time since gravity was last rebuilt",
+            Unit::Seconds,
+            self.gravity_age.clone(),
+        );
+        registry.register(
+            "pihole_nxdomain_24h",
+            "Count of NXDOMAIN replies (24h)",
+            self.nxdomain_24h.clone(),
+        );
+        registry.register(
+            "pihole_nxdomain_rate_24h",
+            "Fraction of queries answered with NXDOMAIN (24h)",
+            self.nxdomain_rate_24h.clone(),
+        );
+        registry.register(
+            "pihole_cache_hit_rate_24h",
+            "Fraction of cached or forwarded queries answered from cache (24h)",
+            self.cache_hit_rate_24h.clone(),
+        );
         registry.register(
             "pihole_query_upstream_count",
             "Total query upstream counts (24h)",
             self.query_upstream_count.clone(),
         );
         registry.register(
-            "pihole_query_type_1m",
-            "Count of query types (last whole 1m)",
-            self.query_type_1m.clone(),
+            "pihole_upstream_count_24h",
+            "Number of distinct upstreams Pi-hole reports for the last 24h",
+            self.upstream_count_24h.clone(),
+        );
+        registry.register(
+            "pihole_upstream_query_entropy",
+            "Shannon entropy in bits of forwarded queries across upstreams (24h), 0 when one upstream takes them all",
+            self.upstream_query_entropy.clone(),
+        );
+        registry.register(
+            "pihole_upstream_count_active",
+            "Number of upstreams that answered at least one query in the last 24h",
+            self.upstream_count_active.clone(),
+        );
+        registry.register(
+            "pihole_upstream_ip_version_count",
+            "Queries forwarded to IPv4 and IPv6 upstreams (24h)",
+            self.upstream_ip_version_count.clone(),
+        );
+        registry.register(
+            "pihole_group_domains_blocked",
+            "Number of domains on enabled blocklists assigned to each group",
+            self.group_domains_blocked.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_blocklist_last_update",
+            "Unix timestamp of the last successful update of each blocklist",
+            Unit::Seconds,
+            self.blocklist_last_update.clone(),
+        );
+        registry.register(
+            "pihole_blocklist_invalid_domains",
+            "Number of invalid entries found in each blocklist",
+            self.blocklist_invalid_domains.clone(),
+        );
+        registry.register(
+            "pihole_queries_in_flight",
+            "Queries received but not yet answered (synthetic=\"true\" when estimated)",
+            self.queries_in_flight.clone(),
+        );
+        registry.register(
+            "pihole_api_version_detected",
+            "Major version of the Pi-hole API detected at startup",
+            self.api_version_detected.clone(),
+        );
+        registry.register(
+            "pihole_api_version_info",
+            "Pi-hole API version detected at startup, always 1",
+            self.api_version_info.clone(),
+        );
+        registry.register(
+            "pihole_auth_method_info",
+            "How the exporter authenticates with Pi-hole, always 1",
+            self.auth_method_info.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_auth_session_age",
+            "Time since the exporter last logged in to Pi-hole",
+            Unit::Seconds,
+            self.auth_session_age.clone(),
+        );
+        registry.register(
+            "pihole_audit_events",
+            "Custom allow/deny domains added or modified since the exporter started",
+            self.audit_events.clone(),
+        );
+        registry.register(
+            "pihole_audit_last_event_timestamp",
+            "Unix timestamp of the most recent custom allow/deny domain change",
+            self.audit_last_event_timestamp.clone(),
         );
         registry.register(
-            "pihole_query_status_1m",
-            "Count of query status (last whole 1m)",
-            self.query_status_1m.clone(),
+            "pihole_custom_allow_list_size",
+            "Number of domains on the custom allow list (exact and regex)",
+            self.custom_allow_list_size.clone(),
         );
         registry.register(
-            "pihole_query_reply_1m",
-            "Count of query reply types (last whole 1m)",
-            self.query_reply_1m.clone(),
+            "pihole_custom_deny_list_size",
+            "Number of domains on the custom deny list (exact and regex)",
+            self.custom_deny_list_size.clone(),
         );
         registry.register(
-            "pihole_query_client_1m",
-            "Count of query clients (last whole 1m)",
-            self.query_client_1m.clone(),
+            "pihole_dhcp_enabled",
+            "Whether the Pi-hole DHCP server is enabled (1=enabled)",
+            self.dhcp_enabled.clone(),
         );
         registry.register(
-            "pihole_query_upstream_1m",
-            "Count of query upstream destinations (last whole 1m)",
-            self.query_upstream_1m.clone(),
+            "pihole_dhcp_leases_total",
+            "Number of active DHCP leases",
+            self.dhcp_leases.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_dhcp_lease_expiry",
+            "Unix timestamp at which each DHCP lease expires",
+            Unit::Seconds,
+            self.dhcp_lease_expiry.clone(),
+        );
+        registry.register(
+            "pihole_ftl_cpu_usage_percent",
+            "CPU usage of the FTL process, in percent of one core",
+            self.ftl_cpu_usage_percent.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_ftl_memory_usage",
+            "Resident memory of the FTL process",
+            Unit::Bytes,
+            self.ftl_memory_usage.clone(),
+        );
+        registry.register(
+            "pihole_database_queries",
+            "Number of queries stored in the long-term database, which FTL prunes after database.maxDBdays",
+            self.database_queries.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_database_earliest_query_timestamp",
+            "Unix timestamp of the oldest query stored in the long-term database",
+            Unit::Seconds,
+            self.database_earliest_query.clone(),
+        );
+        registry.register(
+            "pihole_conditional_forwarding_enabled",
+            "Whether any conditional forwarding target is enabled (1=enabled)",
+            self.conditional_forwarding_enabled.clone(),
+        );
+        registry.register(
+            "pihole_conditional_forwarding_targets",
+            "Number of configured conditional forwarding targets",
+            self.conditional_forwarding_targets.clone(),
+        );
+        for window in &self.windows {
+            window.register(registry);
+        }
+        registry.register(
+            "pihole_queries_in_window",
+            "Number of queries returned by Pi-hole for each window",
+            self.queries_in_window.clone(),
+        );
+        registry.register(
+            "pihole_query_sample_rate",
+            "Fraction of queries counted in window metrics, which are scaled by its inverse",
+            self.query_sample_rate.clone(),
+        );
+        registry.register(
+            "pihole_queries_truncated",
+            "Whether the window hit the query page limit and is undercounted (1=truncated)",
+            self.queries_truncated.clone(),
+        );
+        registry.register(
+            "pihole_historical_queries_total",
+            "Total queries over the long-term history period",
+            self.historical_queries.clone(),
+        );
+        registry.register(
+            "pihole_historical_blocked_total",
+            "Blocked queries over the long-term history period",
+            self.historical_blocked.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_tls_cert_expiry",
+            "Unix timestamp at which the Pi-hole TLS certificate expires",
+            Unit::Seconds,
+            self.tls_cert_expiry.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_disk_usage",
+            "Space used on the filesystem holding the Pi-hole data directory",
+            Unit::Bytes,
+            self.disk_usage.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_disk_free",
+            "Space available on the filesystem holding the Pi-hole data directory",
+            Unit::Bytes,
+            self.disk_free.clone(),
+        );
+        registry.register(
+            "pihole_disk_usage_percent",
+            "Percentage of the filesystem holding the Pi-hole data directory in use",
+            self.disk_usage_percent.clone(),
+        );
+        registry.register(
+            "pihole_api_schema_valid",
+            "Whether the last schema check found every field the exporter reads (1=valid)",
+            self.api_schema_valid.clone(),
+        );
+        registry.register(
+            "pihole_api_schema_check_errors",
+            "Schema checks that could not query a Pi-hole API endpoint",
+            self.api_schema_check_errors.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_api_response_time",
+            "Duration of the most recent call to each Pi-hole API endpoint",
+            Unit::Seconds,
+            self.api_response_time.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_api_response_time_avg",
+            "Average duration of the last 10 calls to each Pi-hole API endpoint",
+            Unit::Seconds,
+            self.api_response_time_avg.clone(),
+        );
+        registry.register(
+            "pihole_cardinality_limit_hits",
+            "Label sets collapsed into the cardinality limit series",
+            self.cardinality_limit_hits.clone(),
+        );
+        registry.register(
+            "pihole_duplicate_queries_skipped",
+            "Queries skipped because their ID was already seen in the same window",
+            self.duplicate_queries_skipped.clone(),
+        );
+        registry.register(
+            "pihole_watchdog_restarts",
+            "Times the watchdog restarted Pi-hole FTL after repeated connection failures",
+            self.watchdog_restarts.clone(),
+        );
+        registry.register(
+            "pihole_stale_scrapes",
+            "Scrapes answered with the last successful metrics because collection failed",
+            self.stale_scrapes.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_stale_scrape_age",
+            "Age of the metrics served by the most recent scrape, 0 when they are fresh",
+            Unit::Seconds,
+            self.stale_scrape_age.clone(),
+        );
+        registry.register(
+            "pihole_last_error_type",
+            "Cause of the last failed update: 0=none, 1=network, 2=auth, 3=parse, 4=timeout",
+            self.last_error_type.clone(),
+        );
+    }
+}
+
+/// Self-instrumentation of the exporter's /metrics handler
+#[derive(Debug)]
+pub struct ExporterSelfMetrics {
+    pub scrapes: Counter,
+    pub scrape_errors: Counter,
+    pub scrape_duration: Histogram,
+    pub config_hash: Gauge<f64, AtomicU64>,
+}
+
+impl ExporterSelfMetrics {
+    /// Create a new instance of ExporterSelfMetrics
+    pub fn new() -> Self {
+        Self {
+            scrapes: Counter::default(),
+            scrape_errors: Counter::default(),
+            scrape_duration: Histogram::new(exponential_buckets(0.005, 2.0, 12)),
+            config_hash: Gauge::default(),
+        }
+    }
+
+    /// Register the metrics, named with a `pihole_exporter` prefix
+    pub fn register(&self, registry: &mut Registry) {
+        self.register_with_descriptions(registry, &MetricDescriptions::default());
+    }
+
+    /// Register the metrics, taking help text from `descriptions` where present
+    pub fn register_with_descriptions(
+        &self,
+        registry: &mut Registry,
+        descriptions: &MetricDescriptions,
+    ) {
+        self.register_described(&mut DescribedRegistry::new(registry, descriptions));
+    }
+
+    /// Register all metrics with a [`DescribedRegistry`]
+    pub fn register_described(&self, registry: &mut DescribedRegistry) {
+        registry.register(
+            "pihole_exporter_scrapes",
+            "Number of /metrics requests handled",
+            self.scrapes.clone(),
+        );
+        registry.register(
+            "pihole_exporter_scrape_errors",
+            "Number of /metrics requests where collecting from Pi-hole failed",
+            self.scrape_errors.clone(),
+        );
+        registry.register_with_unit(
+            "pihole_exporter_scrape_duration",
+            "Time taken to handle a /metrics request",
+            Unit::Seconds,
+            self.scrape_duration.clone(),
+        );
+        registry.register(
+            "pihole_exporter_config_hash",
+            "Hash of the exporter configuration (secrets redacted), changes when it does",
+            self.config_hash.clone(),
         );
     }
 }
 
+impl Default for ExporterSelfMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Default for PiholeMetrics {
     fn default() -> Self {
         Self::new()
@@ -171,4 +1538,220 @@ mod tests {
         // Test that metrics can be created and registered without panicking
         assert_eq!(metrics.domains_being_blocked.get(), 0);
     }
+
+    #[test]
+    fn test_cardinality_limit() {
+        let metrics = PiholeMetrics::with_config(2, &DEFAULT_WINDOW_INTERVALS);
+        let client = |ip: &str| ClientLabels {
+            query_client: ip.to_string(),
+        };
+
+        metrics.windows[0].query_client.set(&client("10.0.0.1"), 1);
+        metrics.windows[0].query_client.set(&client("10.0.0.2"), 2);
+        metrics.windows[0].query_client.set(&client("10.0.0.3"), 3);
+        metrics.windows[0].query_client.set(&client("10.0.0.4"), 4);
+
+        assert_eq!(metrics.cardinality_limit_hits.get(), 2);
+        assert_eq!(
+            metrics.windows[0]
+                .query_client
+                .get_or_create(&client("10.0.0.2"))
+                .get(),
+            2
+        );
+        assert_eq!(
+            metrics.windows[0]
+                .query_client
+                .get_or_create(&ClientLabels::overflow())
+                .get(),
+            7
+        );
+    }
+
+    #[test]
+    fn test_cardinality_report() {
+        let metrics = PiholeMetrics::with_config(2, &[60]);
+        for query_type in ["A", "AAAA", "MX"] {
+            metrics.query_by_type.set(
+                &QueryTypeLabels {
+                    query_type: query_type.to_string(),
+                },
+                1,
+            );
+        }
+
+        let report = metrics.cardinality_report();
+        // Two admitted label sets plus the overflow series
+        assert_eq!(report["pihole_query_by_type"], 3);
+        assert_eq!(report["pihole_query_type_1m"], 0);
+        assert_eq!(report["pihole_dhcp_lease_expiry_seconds"], 0);
+    }
+
+    #[test]
+    fn test_window_clear() {
+        let metrics = PiholeMetrics::with_config(DEFAULT_MAX_LABEL_VALUES, &[60]);
+        let window = &metrics.windows[0];
+        let client = |ip: &str| ClientLabels {
+            query_client: ip.to_string(),
+        };
+
+        window.query_client.set(&client("10.0.0.1"), 3);
+        window.clear();
+        window.query_client.set(&client("10.0.0.2"), 1);
+
+        let mut registry = Registry::default();
+        metrics.register(&mut registry);
+        let mut encoded = String::new();
+        prometheus_client::encoding::text::encode(&mut encoded, &registry).unwrap();
+        assert!(!encoded.contains("10.0.0.1"));
+        assert!(encoded.contains("pihole_query_client_1m{query_client=\"10.0.0.2\"} 1"));
+    }
+
+    #[test]
+    fn test_overflow_resets_each_update_cycle() {
+        let metrics = PiholeMetrics::with_config(1, &[60]);
+        let query_type = |query_type: &str| QueryTypeLabels {
+            query_type: query_type.to_string(),
+        };
+        let overflow = || metrics.query_by_type.get(&QueryTypeLabels::overflow());
+
+        metrics.query_by_type.set(&query_type("A"), 1);
+        assert_eq!(metrics.query_by_type.len(), 1);
+        metrics.query_by_type.set(&query_type("AAAA"), 2);
+        metrics.query_by_type.set(&query_type("MX"), 3);
+        assert_eq!(overflow(), Some(5));
+        assert_eq!(metrics.query_by_type.len(), 2);
+
+        // AAAA is gone from the next update
+        metrics.start_update_cycle();
+        metrics.query_by_type.set(&query_type("MX"), 4);
+        assert_eq!(overflow(), Some(4));
+
+        // The overflow series also counts when only created through `get_or_create`
+        metrics.windows[0]
+            .query_type
+            .get_or_create(&query_type("A"));
+        metrics.windows[0]
+            .query_type
+            .get_or_create(&query_type("MX"));
+        assert_eq!(metrics.windows[0].query_type.len(), 2);
+    }
+
+    #[test]
+    fn test_family_clear_resets_limit() {
+        let family = CardinalityLimitedFamily::<DhcpLeaseLabels, Gauge>::new(
+            1,
+            Counter::default(),
+            Arc::default(),
+        );
+        let lease = |ip: &str| DhcpLeaseLabels {
+            ip: ip.to_string(),
+            hostname: "host".to_string(),
+        };
+
+        family.set(&lease("10.0.0.1"), 1);
+        family.clear();
+        family.set(&lease("10.0.0.2"), 2);
+
+        assert_eq!(family.hits.get(), 0);
+        assert_eq!(family.get_or_create(&lease("10.0.0.2")).get(), 2);
+    }
+
+    #[test]
+    fn test_units_keep_metric_names() {
+        let metrics = PiholeMetrics::new();
+        let mut registry = Registry::default();
+        metrics.register(&mut registry);
+
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &registry).unwrap();
+        assert!(buffer.contains("# UNIT pihole_tls_cert_expiry_seconds seconds"));
+        assert!(buffer.contains("\npihole_tls_cert_expiry_seconds 0\n"));
+    }
+
+    #[test]
+    fn test_metric_descriptions_override_help() {
+        let metrics = PiholeMetrics::new();
+        let mut registry = Registry::default();
+        let descriptions = MetricDescriptions::from_iter([(
+            "pihole_domains_being_blocked".to_string(),
+            "Domains on the gravity blocklist".to_string(),
+        )]);
+        metrics.register_with_descriptions(&mut registry, &descriptions);
+
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &registry).unwrap();
+        assert!(
+            buffer.contains(
+                "# HELP pihole_domains_being_blocked Domains on the gravity blocklist.\n"
+            )
+        );
+        assert!(buffer.contains("# HELP pihole_query_by_type Count of queries by type (24h).\n"));
+    }
+
+    /// Register every metric with `descriptions`, returning the encoded output and
+    /// the names that had no description
+    fn encode_described(descriptions: &MetricDescriptions) -> (String, Vec<String>) {
+        let mut registry = Registry::default();
+        let mut described = DescribedRegistry::new(&mut registry, descriptions);
+        PiholeMetrics::with_config(DEFAULT_MAX_LABEL_VALUES, &[60, 300])
+            .register_described(&mut described);
+        ExporterSelfMetrics::new().register_described(&mut described);
+        let missing = described.missing;
+
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &registry).unwrap();
+        (buffer, missing)
+    }
+
+    #[test]
+    fn test_builtin_metric_descriptions() {
+        for language in METRIC_LANGUAGES {
+            let descriptions = builtin_metric_descriptions(language).unwrap();
+            let (buffer, missing) = encode_described(&descriptions);
+            assert!(
+                missing.is_empty(),
+                "{language} has no help text for {missing:?}"
+            );
+            // Every translated name must match a registered metric
+            for (name, help) in &descriptions {
+                let help = help.replace(WINDOW_PLACEHOLDER, "5m");
+                assert!(
+                    buffer.contains(&format!("{help}.\n")),
+                    "{language} translation of {name} is unused"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_english_descriptions_match_registration() {
+        let english = builtin_metric_descriptions("en").unwrap();
+        assert_eq!(
+            encode_described(&english).0,
+            encode_described(&MetricDescriptions::default()).0
+        );
+    }
+
+    #[test]
+    fn test_exporter_self_metrics_names() {
+        let metrics = ExporterSelfMetrics::new();
+        let mut registry = Registry::default();
+        metrics.register(&mut registry);
+        metrics.scrapes.inc();
+
+        let mut buffer = String::new();
+        prometheus_client::encoding::text::encode(&mut buffer, &registry).unwrap();
+        assert!(buffer.contains("\npihole_exporter_scrapes_total 1\n"));
+        assert!(buffer.contains("\npihole_exporter_scrape_errors_total 0\n"));
+        assert!(buffer.contains("\npihole_exporter_scrape_duration_seconds_count 0\n"));
+    }
+
+    #[test]
+    fn test_window_suffix() {
+        let metrics =
+            PiholeMetrics::with_config(DEFAULT_MAX_LABEL_VALUES, &[900, 60, 90, 3600, 60]);
+        let suffixes: Vec<_> = metrics.windows.iter().map(WindowMetrics::suffix).collect();
+        assert_eq!(suffixes, ["1m", "90s", "15m", "1h"]);
+    }
 }
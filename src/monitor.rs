@@ -0,0 +1,281 @@
+use serde_json::{Value, json};
+
+use crate::Args;
+
+/// Render a Prometheus Operator `ServiceMonitor` that scrapes this exporter
+///
+/// The manifest selects Services labelled `app.kubernetes.io/name: <name>`
+/// and scrapes `/metrics` on the exporter port from `args`.
+pub fn service_monitor_manifest(
+    args: &Args,
+    name: &str,
+    namespace: &str,
+    scrape_interval: &str,
+) -> String {
+    format!(
+        "\
+apiVersion: monitoring.coreos.com/v1
+kind: ServiceMonitor
+metadata:
+  name: {name}
+  namespace: {namespace}
+  labels:
+    app.kubernetes.io/name: {name}
+spec:
+  selector:
+    matchLabels:
+      app.kubernetes.io/name: {name}
+  endpoints:
+    - targetPort: {port}
+      path: /metrics
+      interval: {scrape_interval}
+",
+        port = args.port,
+    )
+}
+
+/// Gravity age in seconds after which PiholeGravityStale fires
+const GRAVITY_STALE_SECS: u64 = 8 * 24 * 3600;
+
+/// Fraction of resolved queries answered from cache below which PiholeLowCacheHitRate fires
+const LOW_CACHE_HIT_RATE: f64 = 0.1;
+
+/// Render a Prometheus rule group alerting on the exporter's metrics
+///
+/// `PiholeDown` fires when the exporter cannot be scraped under `job`, or
+/// serves stale metrics because Pi-hole is unreachable.
+pub fn alert_rules(job: &str, block_rate_threshold: f64, nxdomain_threshold: f64) -> String {
+    format!(
+        "\
+groups:
+  - name: pihole
+    rules:
+      - alert: PiholeDown
+        expr: up{{job=\"{job}\"}} == 0 or pihole_stale_scrape_age_seconds > 0
+        for: 5m
+        labels:
+          severity: critical
+        annotations:
+          summary: Pi-hole {{{{ $labels.instance }}}} is down
+          description: The exporter cannot collect metrics from Pi-hole.
+      - alert: PiholeHighBlockRate
+        expr: pihole_query_count{{category=\"blocked\"}} / ignoring(category) pihole_query_count{{category=\"total\"}} > {block_rate_threshold}
+        for: 30m
+        labels:
+          severity: warning
+        annotations:
+          summary: Pi-hole {{{{ $labels.instance }}}} blocks {{{{ $value | humanizePercentage }}}} of queries
+          description: More than {block_rate_threshold} of queries were blocked over the last 24h.
+      - alert: PiholeGravityStale
+        expr: pihole_gravity_age_seconds > {GRAVITY_STALE_SECS}
+        for: 1h
+        labels:
+          severity: warning
+        annotations:
+          summary: Pi-hole {{{{ $labels.instance }}}} gravity has not been updated
+          description: Gravity was last rebuilt {{{{ $value | humanizeDuration }}}} ago.
+      - alert: PiholeHighNxdomainRate
+        expr: pihole_nxdomain_rate_24h > {nxdomain_threshold}
+        for: 30m
+        labels:
+          severity: warning
+        annotations:
+          summary: Pi-hole {{{{ $labels.instance }}}} answers {{{{ $value | humanizePercentage }}}} of queries with NXDOMAIN
+          description: More than {nxdomain_threshold} of replies were NXDOMAIN over the last 24h.
+      - alert: PiholeLowCacheHitRate
+        expr: pihole_cache_hit_rate_24h < {LOW_CACHE_HIT_RATE}
+        for: 1h
+        labels:
+          severity: info
+        annotations:
+          summary: Pi-hole {{{{ $labels.instance }}}} answers {{{{ $value | humanizePercentage }}}} of queries from cache
+          description: Fewer than {LOW_CACHE_HIT_RATE} of resolved queries were cache hits over the last 24h.
+",
+    )
+}
+
+/// A dashboard panel: title, Grafana panel type, unit, and (PromQL, legend) targets
+type PanelSpec = (
+    &'static str,
+    &'static str,
+    &'static str,
+    &'static [(&'static str, &'static str)],
+);
+
+/// Panels of the generated dashboard, laid out two per row
+const DASHBOARD_PANELS: &[PanelSpec] = &[
+    (
+        "Queries (24h)",
+        "timeseries",
+        "short",
+        &[("pihole_query_count", "{{category}}")],
+    ),
+    (
+        "Block rate (24h)",
+        "stat",
+        "percentunit",
+        &[(
+            "pihole_query_count{category=\"blocked\"} / ignoring(category) pihole_query_count{category=\"total\"}",
+            "blocked",
+        )],
+    ),
+    (
+        "Cache hit rate",
+        "timeseries",
+        "percentunit",
+        &[
+            ("pihole_cache_hit_rate_24h", "24h"),
+            ("pihole_cache_hit_rate_1m", "1m"),
+        ],
+    ),
+    (
+        "Upstream distribution (24h)",
+        "piechart",
+        "short",
+        &[("pihole_query_upstream_count", "{{name}} ({{ip}})")],
+    ),
+    (
+        "Top clients (1m)",
+        "bargauge",
+        "short",
+        &[("topk(10, pihole_query_client_1m)", "{{query_client}}")],
+    ),
+    (
+        "Gravity",
+        "stat",
+        "short",
+        &[
+            ("pihole_domains_being_blocked", "domains blocked"),
+            ("pihole_gravity_age_seconds", "seconds since update"),
+        ],
+    ),
+    (
+        "Query types (1m)",
+        "timeseries",
+        "short",
+        &[("pihole_query_type_1m", "{{query_type}}")],
+    ),
+    (
+        "Query status (1m)",
+        "timeseries",
+        "short",
+        &[("pihole_query_status_1m", "{{query_status}}")],
+    ),
+];
+
+/// Render a Grafana dashboard for the exporter's metrics, querying `datasource`
+pub fn grafana_dashboard(datasource: &str, title: &str) -> String {
+    let panels: Vec<Value> = DASHBOARD_PANELS
+        .iter()
+        .enumerate()
+        .map(|(i, (panel_title, panel_type, unit, targets))| {
+            let targets: Vec<Value> = targets
+                .iter()
+                .zip('A'..)
+                .map(|((expr, legend), ref_id)| {
+                    json!({
+                        "datasource": datasource,
+                        "expr": expr,
+                        "legendFormat": legend,
+                        "refId": ref_id.to_string(),
+                    })
+                })
+                .collect();
+            json!({
+                "id": i + 1,
+                "title": panel_title,
+                "type": panel_type,
+                "datasource": datasource,
+                "gridPos": {"h": 8, "w": 12, "x": (i % 2) * 12, "y": (i / 2) * 8},
+                "fieldConfig": {"defaults": {"unit": unit}, "overrides": []},
+                "targets": targets,
+            })
+        })
+        .collect();
+
+    let dashboard = json!({
+        "title": title,
+        "tags": ["pihole"],
+        "timezone": "browser",
+        "schemaVersion": 39,
+        "refresh": "1m",
+        "time": {"from": "now-6h", "to": "now"},
+        "panels": panels,
+    });
+    serde_json::to_string_pretty(&dashboard).expect("serializing dashboard") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn test_service_monitor_manifest() {
+        let args = Args::parse_from(["pihole-exporter", "--port", "9617"]);
+        let manifest = service_monitor_manifest(&args, "pihole", "dns", "15s");
+
+        assert!(manifest.contains("kind: ServiceMonitor\n"));
+        assert!(manifest.contains("  namespace: dns\n"));
+        assert!(manifest.contains("      app.kubernetes.io/name: pihole\n"));
+        assert!(manifest.contains("    - targetPort: 9617\n"));
+        assert!(manifest.contains("      interval: 15s\n"));
+    }
+
+    #[test]
+    fn test_grafana_dashboard() {
+        let dashboard: Value =
+            serde_json::from_str(&grafana_dashboard("Mimir", "Home DNS")).unwrap();
+
+        assert_eq!(dashboard["title"], "Home DNS");
+        let panels = dashboard["panels"].as_array().unwrap();
+        assert_eq!(panels.len(), DASHBOARD_PANELS.len());
+        assert_eq!(panels[1]["gridPos"]["x"], 12);
+        assert_eq!(panels[2]["gridPos"]["y"], 8);
+        for panel in panels {
+            assert_eq!(panel["datasource"], "Mimir");
+            for target in panel["targets"].as_array().unwrap() {
+                assert!(target["expr"].as_str().unwrap().contains("pihole_"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_alert_rules() {
+        let rules = alert_rules("pihole", 0.5, 0.3);
+        let parsed: serde_yaml::Value = serde_yaml::from_str(&rules).unwrap();
+        let rules = &parsed["groups"][0]["rules"];
+
+        let alerts: Vec<_> = rules
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .map(|rule| rule["alert"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            alerts,
+            [
+                "PiholeDown",
+                "PiholeHighBlockRate",
+                "PiholeGravityStale",
+                "PiholeHighNxdomainRate",
+                "PiholeLowCacheHitRate",
+            ]
+        );
+        assert!(
+            rules[0]["expr"]
+                .as_str()
+                .unwrap()
+                .starts_with("up{job=\"pihole\"} == 0")
+        );
+        assert!(rules[1]["expr"].as_str().unwrap().ends_with("> 0.5"));
+        assert_eq!(
+            rules[3]["expr"].as_str().unwrap(),
+            "pihole_nxdomain_rate_24h > 0.3"
+        );
+        assert_eq!(
+            rules[0]["annotations"]["summary"].as_str().unwrap(),
+            "Pi-hole {{ $labels.instance }} is down"
+        );
+    }
+}
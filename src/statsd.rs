@@ -0,0 +1,179 @@
+use std::net::UdpSocket;
+
+use tracing::{debug, warn};
+
+use crate::Result;
+
+/// Largest payload sent in one datagram, safe for common network MTUs
+const MAX_DATAGRAM_SIZE: usize = 1432;
+
+/// Sends metric values to a DogStatsD agent as gauges over UDP
+///
+/// Only gauges are sent, named after their Prometheus metric with the first `_`
+/// replaced by `.` and labels as tags, e.g. `pihole.query_count:1234|g|#category:total`.
+/// Counters and histograms have no DogStatsD gauge equivalent and are skipped.
+#[derive(Debug)]
+pub struct StatsdEmitter {
+    socket: UdpSocket,
+}
+
+impl StatsdEmitter {
+    /// Create an emitter sending to the DogStatsD agent at `target` (HOST:PORT)
+    pub fn new(target: &str) -> Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self { socket })
+    }
+
+    /// Send every gauge series in the OpenMetrics text `encoded`, batching lines into datagrams
+    ///
+    /// Delivery is fire-and-forget, failures are only logged.
+    pub fn emit(&self, encoded: &str) {
+        let mut datagram = String::new();
+        let mut sent = 0;
+        for (series, value) in gauge_samples(encoded) {
+            let Some(line) = statsd_line(series, value) else {
+                continue;
+            };
+            if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM_SIZE {
+                self.send(&datagram);
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+            sent += 1;
+        }
+        if !datagram.is_empty() {
+            self.send(&datagram);
+        }
+        debug!("Sent {} gauges to DogStatsD", sent);
+    }
+
+    fn send(&self, datagram: &str) {
+        if let Err(e) = self.socket.send(datagram.as_bytes()) {
+            warn!("Failed to send DogStatsD payload: {}", e);
+        }
+    }
+}
+
+/// Samples of the metrics declared `gauge` in the OpenMetrics text `encoded`, as (series, value)
+fn gauge_samples(encoded: &str) -> impl Iterator<Item = (&str, f64)> {
+    let mut gauge = false;
+    encoded.lines().filter_map(move |line| {
+        if let Some(declaration) = line.strip_prefix("# TYPE ") {
+            gauge = declaration.ends_with(" gauge");
+        }
+        if !gauge || line.starts_with('#') {
+            return None;
+        }
+        let (series, value) = line.rsplit_once(' ')?;
+        Some((series, value.parse().ok()?))
+    })
+}
+
+/// Format a series like `pihole_query_count{category="total"}` as a DogStatsD gauge
+fn statsd_line(series: &str, value: f64) -> Option<String> {
+    let (name, labels) = match series.split_once('{') {
+        Some((name, labels)) => (name, labels.strip_suffix('}')?),
+        None => (series, ""),
+    };
+
+    let name = name.replacen('_', ".", 1);
+    let tags = parse_labels(labels)
+        .into_iter()
+        .map(|(key, value)| format!("{key}:{value}"))
+        .collect::<Vec<_>>();
+
+    Some(if tags.is_empty() {
+        format!("{name}:{value}|g")
+    } else {
+        format!("{name}:{value}|g|#{}", tags.join(","))
+    })
+}
+
+/// Parse `key="value",...` into pairs, unescaping the values
+fn parse_labels(labels: &str) -> Vec<(&str, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = labels;
+    while let Some((key, value)) = rest.split_once("=\"") {
+        let mut unescaped = String::new();
+        let mut chars = value.char_indices();
+        let mut end = value.len();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => {
+                    if let Some((_, escaped)) = chars.next() {
+                        unescaped.push(if escaped == 'n' { ' ' } else { escaped });
+                    }
+                }
+                '"' => {
+                    end = i + 1;
+                    break;
+                }
+                // Tag separators are not allowed in DogStatsD tag values
+                ',' | '|' | '#' => unescaped.push('_'),
+                c => unescaped.push(c),
+            }
+        }
+        pairs.push((key, unescaped));
+        rest = value[end..].trim_start_matches(',');
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statsd_line() {
+        assert_eq!(
+            statsd_line("pihole_query_count{category=\"total\"}", 1234.0).as_deref(),
+            Some("pihole.query_count:1234|g|#category:total")
+        );
+        assert_eq!(
+            statsd_line("pihole_domains_being_blocked", 42.0).as_deref(),
+            Some("pihole.domains_being_blocked:42|g")
+        );
+        assert_eq!(
+            statsd_line(
+                "pihole_query_upstream_count{ip=\"1.1.1.1\",name=\"a,\\\"b\",port=\"53\"}",
+                7.0
+            )
+            .as_deref(),
+            Some("pihole.query_upstream_count:7|g|#ip:1.1.1.1,name:a_\"b,port:53")
+        );
+    }
+
+    #[test]
+    fn test_gauge_samples_skip_counters_and_histograms() {
+        let encoded = "\
+# HELP pihole_query_count Query counts by category, 24h.
+# TYPE pihole_query_count gauge
+pihole_query_count{category=\"total\"} 1234
+# HELP pihole_stale_scrapes Scrapes answered with stale metrics.
+# TYPE pihole_stale_scrapes counter
+pihole_stale_scrapes_total 3
+# HELP pihole_exporter_scrape_duration_seconds Time taken to handle a /metrics request.
+# TYPE pihole_exporter_scrape_duration_seconds histogram
+# UNIT pihole_exporter_scrape_duration_seconds seconds
+pihole_exporter_scrape_duration_seconds_sum 0.5
+pihole_exporter_scrape_duration_seconds_count 2
+pihole_exporter_scrape_duration_seconds_bucket{le=\"0.01\"} 1
+# HELP pihole_dhcp_leases_total Number of active DHCP leases.
+# TYPE pihole_dhcp_leases_total gauge
+pihole_dhcp_leases_total 4
+# EOF
+";
+        assert_eq!(
+            gauge_samples(encoded).collect::<Vec<_>>(),
+            [
+                ("pihole_query_count{category=\"total\"}", 1234.0),
+                ("pihole_dhcp_leases_total", 4.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{Resource, trace::SdkTracerProvider};
+use tracing_subscriber::{
+    Layer, filter::LevelFilter, layer::SubscriberExt, util::SubscriberInitExt,
+};
+
+use crate::{PiholeError, Result};
+
+/// Service name reported to the OTLP collector
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Initialize tracing, exporting spans over OTLP/HTTP when an endpoint is given
+///
+/// The returned provider must be shut down on exit to flush pending spans.
+pub fn init_tracing(otlp_endpoint: Option<&str>) -> Result<Option<SdkTracerProvider>> {
+    let fmt = tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO);
+
+    let Some(endpoint) = otlp_endpoint else {
+        tracing_subscriber::registry().with(fmt).init();
+        return Ok(None);
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| PiholeError::Telemetry(e.to_string()))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(SERVICE_NAME).build())
+        .build();
+
+    let otel = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(SERVICE_NAME))
+        .with_filter(LevelFilter::INFO);
+
+    tracing_subscriber::registry().with(fmt).with(otel).init();
+
+    Ok(Some(provider))
+}
@@ -0,0 +1,56 @@
+use tokio::net::TcpStream;
+use tokio_native_tls::TlsConnector;
+use x509_parser::prelude::{FromDer, X509Certificate};
+
+use crate::{PiholeError, Result};
+
+/// Default port used when the Pi-hole host does not specify one
+const DEFAULT_TLS_PORT: u16 = 443;
+
+/// Split a `host[:port]` string into its host and port parts
+fn split_host_port(host: &str) -> (&str, u16) {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') => match port.parse() {
+            Ok(port) => (name.trim_matches(['[', ']']), port),
+            Err(_) => (host, DEFAULT_TLS_PORT),
+        },
+        _ => (host, DEFAULT_TLS_PORT),
+    }
+}
+
+/// Fetch the certificate served by `host` and return its `not_after` as a Unix timestamp
+pub async fn certificate_expiry(host: &str) -> Result<i64> {
+    let (name, port) = split_host_port(host);
+
+    // Pi-hole commonly uses self-signed certificates, we only want to read it
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()?;
+    let connector = TlsConnector::from(connector);
+
+    let stream = TcpStream::connect((name, port)).await?;
+    let stream = connector.connect(name, stream).await?;
+
+    let certificate = stream
+        .get_ref()
+        .peer_certificate()?
+        .ok_or_else(|| PiholeError::Tls("Pi-hole did not present a TLS certificate".to_string()))?;
+    let der = certificate.to_der()?;
+    let (_, certificate) =
+        X509Certificate::from_der(&der).map_err(|e| PiholeError::Tls(e.to_string()))?;
+
+    Ok(certificate.validity().not_after.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("pi.hole"), ("pi.hole", 443));
+        assert_eq!(split_host_port("192.168.1.2:8443"), ("192.168.1.2", 8443));
+        assert_eq!(split_host_port("[::1]:8443"), ("::1", 8443));
+    }
+}
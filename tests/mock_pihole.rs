@@ -0,0 +1,321 @@
+//! Integration tests against an in-process mock Pi-hole serving `tests/fixtures/*.json`
+
+use std::{fs, path::Path, sync::Arc, time::Duration};
+
+use axum::{
+    Json, Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use clap::Parser;
+use pihole_exporter::{
+    Args, PiholeCollector, PiholeError, domain_query_handler, handlers::QueryByDomainParams,
+    metrics::QueryTypeLabels, metrics_handler, metrics_streaming_handler,
+};
+use serde_json::{Value, json};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+/// Password the mock accepts at `/api/auth`
+const MOCK_PASSWORD: &str = "secret";
+
+/// Session ID issued by `tests/fixtures/auth.json`
+const MOCK_SID: &str = "mock-sid";
+
+/// Load a JSON fixture from `tests/fixtures`
+fn fixture(name: &str) -> Value {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    let contents = fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("reading fixture {}: {e}", path.display()));
+    serde_json::from_str(&contents).expect("parsing fixture")
+}
+
+/// Handler for `/api/auth`, issuing [`MOCK_SID`] for [`MOCK_PASSWORD`]
+async fn auth(Json(request): Json<Value>) -> (StatusCode, Json<Value>) {
+    if request["password"] == MOCK_PASSWORD {
+        (StatusCode::OK, Json(fixture("auth.json")))
+    } else {
+        (StatusCode::UNAUTHORIZED, Json(fixture("auth_invalid.json")))
+    }
+}
+
+/// Route serving `name` to requests that carry the mock session ID
+fn authenticated(name: &'static str) -> axum::routing::MethodRouter {
+    get(move |headers: HeaderMap| async move {
+        if headers.get("sid").is_some_and(|sid| sid == MOCK_SID) {
+            (StatusCode::OK, Json(fixture(name)))
+        } else {
+            (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": {"key": "unauthorized", "message": "Unauthorized"}})),
+            )
+        }
+    })
+}
+
+/// Authenticated `GET` routes served by the mock, as (path, fixture)
+const MOCK_ROUTES: &[(&str, &str)] = &[
+    ("/api/stats/summary", "stats_summary.json"),
+    ("/api/stats/upstreams", "stats_upstreams.json"),
+    ("/api/queries", "queries.json"),
+    ("/api/groups", "groups.json"),
+    ("/api/lists", "lists.json"),
+    ("/api/domains", "domains.json"),
+    ("/api/config/dns/revServers", "config_dns_rev_servers.json"),
+    ("/api/info/version", "info_version.json"),
+    ("/api/info/ftl", "info_ftl.json"),
+    ("/api/info/database", "info_database.json"),
+    ("/api/info/system", "info_system.json"),
+];
+
+/// Start a mock Pi-hole on a free local port
+///
+/// Returns the `host:port` to pass as `--pihole`, and the server task.
+async fn start_mock_pihole() -> (String, JoinHandle<()>) {
+    start_mock_pihole_without(&[]).await
+}
+
+/// Start a mock Pi-hole that answers 404 for the `missing` paths
+async fn start_mock_pihole_without(missing: &[&str]) -> (String, JoinHandle<()>) {
+    serve_mock(mock_router(missing)).await
+}
+
+/// Mock Pi-hole routes, leaving out the `missing` paths
+fn mock_router(missing: &[&str]) -> Router {
+    MOCK_ROUTES
+        .iter()
+        .filter(|(path, _)| !missing.contains(path))
+        .fold(
+            Router::new().route("/api/auth", post(auth)),
+            |app, (path, name)| app.route(path, authenticated(name)),
+        )
+}
+
+/// Serve `app` on a free local port
+async fn serve_mock(app: Router) -> (String, JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let server = tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+    (addr, server)
+}
+
+/// Exporter arguments pointing at the mock Pi-hole at `addr`
+fn mock_args(addr: &str, password: Option<&str>) -> Args {
+    let mut argv = vec![
+        "pihole-exporter",
+        "--pihole",
+        addr,
+        "--schema-check-interval-secs",
+        "0",
+    ];
+    if let Some(password) = password {
+        argv.extend(["--password", password]);
+    }
+    Args::parse_from(argv)
+}
+
+#[tokio::test]
+async fn test_collects_metrics_from_mock() {
+    let (addr, server) = start_mock_pihole().await;
+    let collector = PiholeCollector::new(&mock_args(&addr, Some(MOCK_PASSWORD)))
+        .await
+        .unwrap();
+    assert_eq!(collector.sid().as_deref(), Some(MOCK_SID));
+
+    collector.update_metrics().await.unwrap();
+    let encoded = collector.encode_metrics().unwrap();
+
+    for line in [
+        "pihole_query_count{category=\"total\"} 7497",
+        "pihole_client_count{category=\"active\"} 10",
+        "pihole_domains_being_blocked 104584",
+        "pihole_conditional_forwarding_enabled 1",
+        "pihole_ftl_cpu_usage_percent 0.8",
+        "pihole_ftl_memory_usage_bytes 15360000",
+        "pihole_database_queries 612345",
+        "pihole_database_earliest_query_timestamp_seconds 1717484400",
+        "pihole_cache_hit_rate_24h 0.005704",
+        "pihole_upstream_ip_version_count{version=\"v6\"} 1009",
+        "pihole_api_version_detected 6",
+        "pihole_auth_method_info{method=\"password\"} 1",
+        "pihole_last_error_type 0",
+        "pihole_query_type_1m{query_type=\"A\"} 2",
+        "pihole_upstream_cache_ratio_1m{upstream=\"1.1.1.1#53\",served_from=\"upstream\"} 1.0",
+        "pihole_upstream_cache_ratio_1m{upstream=\"cache\",served_from=\"cache\"} 1.0",
+        "pihole_query_rcode_24h{rcode=\"NOERROR\"} 7497",
+        "pihole_query_client_1m{query_client=\"192.168.1.10\"} 2",
+        "pihole_special_domain_queries_1m 1",
+        "pihole_special_domain_types_1m{query_type=\"HTTPS\"} 1",
+    ] {
+        assert!(encoded.contains(line), "missing `{line}` in:\n{encoded}");
+    }
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_optional_endpoint_failures_keep_core_metrics() {
+    let (addr, server) = start_mock_pihole_without(&[
+        "/api/groups",
+        "/api/lists",
+        "/api/domains",
+        "/api/config/dns/revServers",
+        "/api/info/ftl",
+        "/api/info/system",
+        "/api/info/database",
+    ])
+    .await;
+    let collector = PiholeCollector::new(&mock_args(&addr, Some(MOCK_PASSWORD)))
+        .await
+        .unwrap();
+
+    collector.update_metrics().await.unwrap();
+    let encoded = collector.encode_metrics().unwrap();
+    assert!(encoded.contains("pihole_query_count{category=\"total\"} 7497"));
+    assert!(!encoded.contains("pihole_group_domains_blocked{"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_not_modified_without_cached_response_fails() {
+    let app = mock_router(&["/api/stats/summary"]).route(
+        "/api/stats/summary",
+        get(|| async { StatusCode::NOT_MODIFIED }),
+    );
+    let (addr, server) = serve_mock(app).await;
+    // Startup validation fetches the summary
+    let error = PiholeCollector::new(&mock_args(&addr, Some(MOCK_PASSWORD)))
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root(), PiholeError::InvalidResponse(_)));
+    assert!(error.to_string().contains("304 Not Modified"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_refreshes_expiring_session() {
+    let (addr, server) = start_mock_pihole().await;
+    let mut args = mock_args(&addr, Some(MOCK_PASSWORD));
+    args.session_timeout_secs = 1;
+    let collector = PiholeCollector::new(&args).await.unwrap();
+
+    assert!(!collector.refresh_auth_if_expiring().await.unwrap());
+    tokio::time::sleep(collector.session_timeout * 4 / 5).await;
+    assert!(collector.refresh_auth_if_expiring().await.unwrap());
+    assert!(!collector.refresh_auth_if_expiring().await.unwrap());
+    assert_eq!(collector.sid().as_deref(), Some(MOCK_SID));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_stalled_stream_does_not_block_scrapes() {
+    let (addr, server) = start_mock_pihole().await;
+    let mut args = mock_args(&addr, Some(MOCK_PASSWORD));
+    args.max_label_values = 20_000;
+    let collector = Arc::new(PiholeCollector::new(&args).await.unwrap());
+    // Enough series for the stream to span many chunks
+    for i in 0..10_000 {
+        collector.metrics.query_by_type.set(
+            &QueryTypeLabels {
+                query_type: format!("TYPE{i}"),
+            },
+            i,
+        );
+    }
+
+    // Never polled, like a client that stopped reading
+    let stalled = metrics_streaming_handler(State(collector.clone())).await;
+    assert_eq!(stalled.status(), StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(collector.registry.try_lock().is_ok());
+
+    let scrape = tokio::time::timeout(
+        Duration::from_secs(10),
+        metrics_handler(State(collector.clone())),
+    )
+    .await
+    .expect("scrape blocked by a stalled stream");
+    assert_eq!(scrape.status(), StatusCode::OK);
+
+    drop(stalled);
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_stream_sends_every_family() {
+    let (addr, server) = start_mock_pihole().await;
+    let collector = Arc::new(
+        PiholeCollector::new(&mock_args(&addr, Some(MOCK_PASSWORD)))
+            .await
+            .unwrap(),
+    );
+
+    let response = metrics_streaming_handler(State(collector.clone())).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("pihole_query_count{category=\"total\"} 7497"));
+    assert!(body.contains("# TYPE pihole_exporter_config_hash gauge"));
+    assert!(body.ends_with("# EOF\n"));
+    assert_eq!(body.matches("# EOF").count(), 1);
+
+    // The scrape is timed once the stream has ended
+    let encoded = collector.encode_metrics().unwrap();
+    assert!(encoded.contains("pihole_exporter_scrape_duration_seconds_count 1\n"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_domain_query_skips_endpoint_timing() {
+    let (addr, server) = start_mock_pihole().await;
+    let collector = Arc::new(
+        PiholeCollector::new(&mock_args(&addr, Some(MOCK_PASSWORD)))
+            .await
+            .unwrap(),
+    );
+
+    let response = domain_query_handler(
+        State(collector.clone()),
+        Query(QueryByDomainParams {
+            domain: "example.com".to_string(),
+            length: None,
+        }),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let encoded = collector.encode_metrics().unwrap();
+    assert!(!encoded.contains("endpoint=\"queries\""));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_rejects_wrong_password() {
+    let (addr, server) = start_mock_pihole().await;
+    let error = PiholeCollector::new(&mock_args(&addr, Some("wrong")))
+        .await
+        .unwrap_err();
+    assert!(matches!(error.root(), PiholeError::Auth(_)));
+    assert!(error.to_string().starts_with("logging in to http://"));
+
+    server.abort();
+}
+
+#[tokio::test]
+async fn test_unauthenticated_startup_fails_validation() {
+    let (addr, server) = start_mock_pihole().await;
+    let result = PiholeCollector::new(&mock_args(&addr, None)).await;
+    assert!(matches!(result, Err(PiholeError::InvalidResponse(_))));
+
+    server.abort();
+}